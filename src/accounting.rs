@@ -1,75 +1,1848 @@
-use chrono::prelude::{Date, Utc};
+use chrono::NaiveDate;
+use error::Diagnostic;
+use num::{pow, BigInt, BigRational, One, Signed, Zero};
+use parser::{self, Options};
+use period::Period;
+use query::Query;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap};
+use std::fmt;
+use std::io::Error;
+use std::iter;
+use std::mem;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
 use status::Status;
 
 #[derive(Debug, Clone)]
 pub struct Account {
     name: AccountName,
+    declared: bool,
+    note: Option<String>,
+    aliases: Vec<AccountName>,
+    payees: Vec<String>,
+    checks: Vec<String>,
+    assertions: Vec<String>,
+    default: bool,
+    postings: Vec<PostingId>,
 }
 
-type AccountName = String;
+pub type AccountName = String;
+
+/// A transaction in a `Journal`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TransactionId(usize);
+
+/// A posting in a `Journal`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PostingId(usize);
+
+/// An account in a `Journal`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AccountId(usize);
+
+/// A commodity such as `$` or `AAPL`, or none for a plain number. Unlike the other ids, which
+/// belong to a journal, commodity symbols are interned once for the whole program, so that an
+/// amount can be shown on its own and amounts from any journal can be added together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CommodityId(usize);
+
+impl CommodityId {
+    /// The commodity of plain numbers, whose symbol is empty.
+    pub const NONE: CommodityId = CommodityId(0);
+
+    /// The commodity with this symbol, interning the symbol the first time it is seen.
+    pub fn intern(symbol: &str) -> CommodityId {
+        if let Some(&id) = commodity_pool().read().unwrap().ids.get(symbol) {
+            return id;
+        }
+        let mut pool = commodity_pool().write().unwrap();
+        if let Some(&id) = pool.ids.get(symbol) {
+            return id;
+        }
+        // Symbols are never freed, so each is leaked once and shared from then on.
+        let symbol: &'static str = Box::leak(symbol.to_string().into_boxed_str());
+        let id = CommodityId(pool.symbols.len());
+        pool.symbols.push(symbol);
+        pool.ids.insert(symbol, id);
+        id
+    }
+    /// The commodity with this symbol if it has been seen, without interning it. Lookups by a
+    /// symbol the caller supplies go through here, so that they do not grow the pool.
+    pub fn find(symbol: &str) -> Option<CommodityId> {
+        commodity_pool().read().unwrap().ids.get(symbol).cloned()
+    }
+    pub fn symbol(self) -> &'static str {
+        commodity_pool().read().unwrap().symbols[self.0]
+    }
+}
+
+struct CommodityPool {
+    symbols: Vec<&'static str>,
+    ids: HashMap<&'static str, CommodityId>,
+}
+
+fn commodity_pool() -> &'static RwLock<CommodityPool> {
+    static POOL: OnceLock<RwLock<CommodityPool>> = OnceLock::new();
+    POOL.get_or_init(|| {
+        let mut ids = HashMap::new();
+        ids.insert("", CommodityId::NONE);
+        RwLock::new(CommodityPool { symbols: vec![""], ids })
+    })
+}
+
+/// A file a `Journal` was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId(usize);
+
+/// Everything read from a journal: the transactions and their postings in the order they were
+/// written, every account, whether declared with an `account` directive or only used in a
+/// posting, and the commodities declared with `commodity`.
+///
+/// The journal owns each of these once, and they refer to one another by id: a transaction has
+/// the ids of its postings, and a posting those of its transaction and account. Its queries only
+/// borrow from it, so a journal that has been read can be shared between threads.
+#[derive(Debug, Default)]
+pub struct Journal {
+    transactions: Vec<Transaction>,
+    postings: Vec<Posting>,
+    accounts: Vec<Account>,
+    account_ids: BTreeMap<AccountName, AccountId>,
+    // Only the declared commodities.
+    commodities: BTreeMap<CommodityId, Commodity>,
+    // How amounts in each commodity have been written, for commodities without a `format`.
+    styles: BTreeMap<CommodityId, CommodityStyle>,
+    files: Vec<PathBuf>,
+    file_ids: BTreeMap<PathBuf, FileId>,
+    payees: BTreeMap<String, Payee>,
+    tags: BTreeMap<TagName, Tag>,
+    prices: PriceHistory,
+    conversions: BTreeMap<CommodityId, (CommodityId, Quantity)>,
+    automated_transactions: Vec<AutomatedTransaction>,
+    periodic_transactions: Vec<PeriodicTransaction>,
+    // The errors and warnings found while reading it, in the order they were found.
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Journal {
+    pub fn new() -> Journal {
+        Journal::default()
+    }
+    /// Reads a journal file, along with every file it includes, with the default options. Errors
+    /// in the journal do not fail it; see `diagnostics`.
+    pub fn load(path: &Path) -> Result<Journal, Error> {
+        parser::parse_file(path, &Options::new())
+    }
+    /// The errors and warnings found while reading the journal. Lines with errors are left out
+    /// of it.
+    pub fn diagnostics(&self) -> &Vec<Diagnostic> {
+        &self.diagnostics
+    }
+    pub fn add_diagnostic(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+    pub fn transactions(&self) -> &Vec<Transaction> {
+        &self.transactions
+    }
+    pub fn transaction(&self, id: TransactionId) -> &Transaction {
+        &self.transactions[id.0]
+    }
+    pub fn transaction_mut(&mut self, id: TransactionId) -> &mut Transaction {
+        &mut self.transactions[id.0]
+    }
+    /// Adds a transaction without any postings, which `add_posting` then adds to it.
+    pub fn add_transaction(&mut self, mut transaction: Transaction) -> TransactionId {
+        transaction.postings.clear();
+        self.transactions.push(transaction);
+        TransactionId(self.transactions.len() - 1)
+    }
+    pub fn postings(&self) -> &Vec<Posting> {
+        &self.postings
+    }
+    pub fn posting(&self, id: PostingId) -> &Posting {
+        &self.postings[id.0]
+    }
+    /// Adds a posting to the end of its transaction. The posting takes on those tags of the
+    /// transaction that its own note does not set.
+    pub fn add_posting(&mut self, mut posting: Posting) -> PostingId {
+        let id = PostingId(self.postings.len());
+        let transaction = &mut self.transactions[posting.transaction.0];
+        for (name, value) in &transaction.tags {
+            posting.tags.entry(name.clone()).or_insert_with(|| value.clone());
+        }
+        transaction.postings.push(id);
+        self.accounts[posting.account.0].postings.push(id);
+        self.postings.push(posting);
+        id
+    }
+    /// Every account, ordered by name.
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.account_ids.values().map(move |id| &self.accounts[id.0])
+    }
+    pub fn account(&self, id: AccountId) -> &Account {
+        &self.accounts[id.0]
+    }
+    pub fn find_account(&self, name: &str) -> Option<AccountId> {
+        self.account_ids.get(name).cloned()
+    }
+    /// The account with this name, which is added undeclared if the journal has no such account.
+    pub fn add_account(&mut self, name: &str) -> AccountId {
+        if let Some(id) = self.find_account(name) {
+            return id;
+        }
+        self.accounts.push(Account::new(name.to_string()));
+        let id = AccountId(self.accounts.len() - 1);
+        self.account_ids.insert(name.to_string(), id);
+        id
+    }
+    /// Declaring an account that has already been used keeps its id but replaces what is known
+    /// about it.
+    pub fn declare_account(&mut self, mut account: Account) -> AccountId {
+        account.declared = true;
+        let id = self.add_account(&account.name);
+        account.postings = mem::take(&mut self.accounts[id.0].postings);
+        self.accounts[id.0] = account;
+        id
+    }
+    /// Accounts that postings use but no `account` directive declares, such as a misspelt
+    /// `Expences:Food`.
+    pub fn undeclared_accounts(&self) -> Vec<&Account> {
+        self.accounts().filter(|account| !account.declared).collect()
+    }
+    /// The postings to an account, in the order they were written.
+    pub fn account_postings(&self, account: AccountId) -> impl Iterator<Item = &Posting> {
+        self.accounts[account.0].postings.iter().map(move |id| &self.postings[id.0])
+    }
+    /// The transactions dated from `begin` up to but not including `end`, in the order they were
+    /// written.
+    pub fn transactions_between(&self, begin: NaiveDate, end: NaiveDate) -> impl Iterator<Item = &Transaction> {
+        self.transactions.iter().filter(move |transaction| transaction.date >= begin && transaction.date < end)
+    }
+    /// The total of an account and the accounts below it, counting postings dated up to and
+    /// including `date`. Amounts are in the smallest unit each commodity converts into, as
+    /// `format_balance` expects.
+    pub fn balance_as_of(&self, account: AccountId, date: NaiveDate) -> Balance {
+        let below = format!("{}:", self.accounts[account.0].name);
+        let accounts = self.account_ids.range(below.clone()..)
+            .take_while(|&(name, _)| name.starts_with(&below))
+            .map(|(_, &id)| id);
+        let mut balance = Balance::new();
+        for id in iter::once(account).chain(accounts) {
+            for posting in self.account_postings(id).filter(|posting| self.transactions[posting.transaction.0].date <= date) {
+                if let Some(amount) = posting.amount() {
+                    balance += self.reduce(amount);
+                }
+            }
+        }
+        balance
+    }
+    /// The payee of every transaction, each once and in order.
+    pub fn payees(&self) -> impl Iterator<Item = &String> {
+        self.transactions.iter().map(|transaction| &transaction.payee).collect::<BTreeSet<&String>>().into_iter()
+    }
+    /// Every declared commodity, ordered by symbol.
+    pub fn commodities(&self) -> impl Iterator<Item = &Commodity> {
+        let mut commodities = self.commodities.values().collect::<Vec<&Commodity>>();
+        commodities.sort_by_key(|commodity| commodity.symbol());
+        commodities.into_iter()
+    }
+    /// The declaration of a commodity, if it has one.
+    pub fn commodity(&self, id: CommodityId) -> Option<&Commodity> {
+        self.commodities.get(&id)
+    }
+    /// The declared commodity with this symbol.
+    pub fn find_commodity(&self, symbol: &str) -> Option<CommodityId> {
+        CommodityId::find(symbol).filter(|id| self.commodities.contains_key(id))
+    }
+    /// Declaring a commodity again replaces what is known about it. Declaring a default
+    /// commodity makes it the only one.
+    pub fn declare_commodity(&mut self, commodity: Commodity) -> CommodityId {
+        if commodity.default {
+            for other in self.commodities.values_mut() {
+                other.default = false;
+            }
+        }
+        let id = commodity.id;
+        self.commodities.insert(id, commodity);
+        id
+    }
+    /// The commodity of amounts written as bare numbers, from `D` or a `commodity` with `default`.
+    pub fn default_commodity(&self) -> Option<&Commodity> {
+        self.commodities.values().find(|commodity| commodity.default)
+    }
+    /// The file with this path, which is added if the journal has no such file.
+    pub fn add_file(&mut self, path: &Path) -> FileId {
+        if let Some(&id) = self.file_ids.get(path) {
+            return id;
+        }
+        self.files.push(path.to_path_buf());
+        let id = FileId(self.files.len() - 1);
+        self.file_ids.insert(path.to_path_buf(), id);
+        id
+    }
+    pub fn file(&self, id: FileId) -> &Path {
+        &self.files[id.0]
+    }
+    /// Payees declared with the `payee` directive.
+    pub fn declared_payees(&self) -> impl Iterator<Item = &Payee> {
+        self.payees.values()
+    }
+    pub fn is_payee_declared(&self, payee: &str) -> bool {
+        self.payees.contains_key(payee)
+    }
+    pub fn declare_payee(&mut self, payee: Payee) {
+        self.payees.insert(payee.name.clone(), payee);
+    }
+    /// Tags declared with the `tag` directive.
+    pub fn declared_tags(&self) -> impl Iterator<Item = &Tag> {
+        self.tags.values()
+    }
+    pub fn is_tag_declared(&self, tag: &str) -> bool {
+        self.tags.contains_key(tag)
+    }
+    pub fn declare_tag(&mut self, tag: Tag) {
+        self.tags.insert(tag.name.clone(), tag);
+    }
+    pub fn prices(&self) -> &PriceHistory {
+        &self.prices
+    }
+    pub fn add_price(&mut self, price: Price) {
+        self.prices.add(price);
+    }
+    /// An amount's market value on a date, in `target` or, without one, in whatever its commodity
+    /// was most recently priced in. Amounts without a price keep their own commodity.
+    pub fn market_value(&self, amount: &Amount, target: Option<&str>, date: NaiveDate) -> Amount {
+        if target == Some(amount.symbol()) {
+            return amount.clone();
+        }
+        let value = match target {
+            Some(target) => convert(&self.prices, amount, target, date),
+            None => self.prices.latest(amount.symbol(), date)
+                .map(|price| amount.clone().with_commodity(CommodityId::NONE) * price.price),
+        };
+        value.unwrap_or_else(|| amount.clone())
+    }
+    pub fn automated_transactions(&self) -> &Vec<AutomatedTransaction> {
+        &self.automated_transactions
+    }
+    pub fn add_automated_transaction(&mut self, automated: AutomatedTransaction) {
+        self.automated_transactions.push(automated);
+    }
+    pub fn periodic_transactions(&self) -> &Vec<PeriodicTransaction> {
+        &self.periodic_transactions
+    }
+    pub fn add_periodic_transaction(&mut self, periodic: PeriodicTransaction) {
+        self.periodic_transactions.push(periodic);
+    }
+    /// Records that `larger` is worth `smaller`, as in `C 1h = 60m`, so that amounts in either
+    /// unit can be added together.
+    pub fn add_conversion(&mut self, larger: &Amount, smaller: &Amount) -> Result<(), String> {
+        if larger.commodity == smaller.commodity || larger.is_zero() || smaller.is_zero() {
+            return Err(format!("Cannot convert {} into {}", self.format_amount(larger), self.format_amount(smaller)));
+        }
+        if self.reduce(smaller).commodity == larger.commodity {
+            return Err(format!("Converting {} into {} would go round in circles", larger.symbol(), smaller.symbol()));
+        }
+        self.conversions.insert(larger.commodity, (smaller.commodity, &smaller.quantity / &larger.quantity));
+        Ok(())
+    }
+    /// An amount in the smallest unit it converts into, such as `90m` for `1.5h`.
+    pub fn reduce(&self, amount: &Amount) -> Amount {
+        let mut amount = amount.clone();
+        while let Some((smaller, factor)) = self.conversions.get(&amount.commodity) {
+            amount.commodity = *smaller;
+            amount.quantity = &amount.quantity * factor;
+        }
+        amount
+    }
+    /// The opposite of `reduce`: an amount in the largest unit of which there is at least one,
+    /// such as `1.5h` for `90m` but `30m` for `30m`.
+    pub fn unreduce(&self, amount: &Amount) -> Amount {
+        let mut amount = self.reduce(amount);
+        let mut best: Option<(CommodityId, Quantity)> = None;
+        for &larger in self.conversions.keys() {
+            // How many of the reduced unit one of `larger` is.
+            let unit = self.reduce(&Amount::new(larger, Quantity::one()));
+            if unit.commodity != amount.commodity || best.as_ref().is_some_and(|best| best.1 >= unit.quantity) {
+                continue;
+            }
+            if (&amount.quantity / &unit.quantity).abs() >= Quantity::one() {
+                best = Some((larger, unit.quantity));
+            }
+        }
+        if let Some((larger, factor)) = best {
+            amount.commodity = larger;
+            amount.quantity = &amount.quantity / &factor;
+        }
+        amount
+    }
+    /// The sum of two amounts, which must be in the same commodity or convert into the same unit.
+    pub fn add_amounts(&self, left: &Amount, right: &Amount) -> Option<Amount> {
+        if left.commodity == right.commodity {
+            return Some(left.clone() + right.clone());
+        }
+        let (left, right) = (self.reduce(left), self.reduce(right));
+        if left.commodity != right.commodity {
+            return None;
+        }
+        Some(self.unreduce(&(left + right)))
+    }
+    /// The style amounts in a commodity are shown in: as declared by its `format`, as learned
+    /// from the amounts written in it, or otherwise a guess from the symbol.
+    pub fn style(&self, commodity: CommodityId) -> CommodityStyle {
+        self.commodity(commodity)
+            .and_then(|declared| declared.style.clone())
+            .or_else(|| self.styles.get(&commodity).cloned())
+            .unwrap_or_else(|| CommodityStyle::default_for(commodity.symbol()))
+    }
+    /// Learns the style of a commodity from an amount written in it, such as `$1,000.00`. The
+    /// first amount settles where the symbol goes, and the style takes on the greatest precision.
+    pub fn learn_style(&mut self, commodity: CommodityId, style: CommodityStyle) {
+        match self.styles.get_mut(&commodity) {
+            Some(learned) => learned.learn(&style),
+            None => {
+                self.styles.insert(commodity, style);
+            }
+        }
+    }
+    /// The amount in the style its commodity is declared or written in. Only the journal knows
+    /// that style, so this is how an amount is shown; it has no `Display` of its own.
+    pub fn format_amount(&self, amount: &Amount) -> String {
+        self.style(amount.commodity).format(amount.symbol(), amount.quantity())
+    }
+    /// A balance as stacked lines, one per commodity in the style of each, leaving out amounts
+    /// that would be shown as zero. A balance of nothing else is a lone `0`.
+    pub fn format_balance(&self, balance: &Balance) -> Vec<String> {
+        let lines = balance.round(|commodity| self.style(commodity).precision()).amounts()
+            .map(|amount| self.format_amount(&self.unreduce(amount)))
+            .collect::<Vec<String>>();
+        if lines.is_empty() {
+            vec!["0".to_string()]
+        }
+        else {
+            lines
+        }
+    }
+}
+
+/// A virtual posting, written `(Account)`, need not balance; a balanced virtual posting, written
+/// `[Account]`, must balance with the other balanced virtual postings of its transaction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PostingKind {
+    Real,
+    Virtual,
+    BalancedVirtual,
+}
 
 #[derive(Debug, Clone)]
 pub struct Posting {
-    transaction: Transaction,
-    account: Account,
+    transaction: TransactionId,
+    account: AccountId,
+    kind: PostingKind,
     amount: Option<Amount>,
+    cost: Option<Amount>,
+    note: Option<String>,
+    tags: Tags,
+    generated: bool,
 }
 
 impl Posting {
-    pub fn new(transaction: Transaction, account: Account, amount: Option<Amount>) -> Posting {
+    pub fn new(transaction: TransactionId, account: AccountId, amount: Option<Amount>) -> Posting {
         Posting {
-            transaction: transaction,
-            account: account,
-            amount: amount,
+            transaction,
+            account,
+            kind: PostingKind::Real,
+            amount,
+            cost: None,
+            note: None,
+            tags: Tags::new(),
+            generated: false,
+        }
+    }
+    pub fn transaction(&self) -> TransactionId {
+        self.transaction
+    }
+    pub fn account(&self) -> AccountId {
+        self.account
+    }
+    pub fn kind(&self) -> PostingKind {
+        self.kind
+    }
+    pub fn set_kind(&mut self, kind: PostingKind) {
+        self.kind = kind;
+    }
+    pub fn amount(&self) -> Option<&Amount> {
+        self.amount.as_ref()
+    }
+    pub fn set_amount(&mut self, amount: Option<Amount>) {
+        self.amount = amount;
+    }
+    /// The price paid for each unit of the amount, from `@` or `@@` in the journal.
+    pub fn cost(&self) -> Option<&Amount> {
+        self.cost.as_ref()
+    }
+    pub fn set_cost(&mut self, cost: Option<Amount>) {
+        self.cost = cost;
+    }
+    /// What the posting contributes to balancing its transaction: the amount, or at a cost the
+    /// amount times the cost.
+    pub fn value(&self) -> Option<Amount> {
+        match (&self.amount, &self.cost) {
+            (Some(amount), Some(cost)) => Some(amount.clone().with_commodity(CommodityId::NONE) * cost.clone()),
+            (amount, _) => amount.clone(),
         }
     }
+    pub fn note(&self) -> Option<&String> {
+        self.note.as_ref()
+    }
+    pub fn add_note(&mut self, note: &str) {
+        add_note(&mut self.note, note);
+    }
+    pub fn tags(&self) -> &Tags {
+        &self.tags
+    }
+    pub fn set_tag(&mut self, name: TagName, value: Option<TagValue>) {
+        self.tags.insert(name, value);
+    }
+    /// Whether an automated transaction added this posting, rather than the journal itself.
+    pub fn is_generated(&self) -> bool {
+        self.generated
+    }
 }
 
+/// A block starting `= PREDICATE` whose postings are added to every transaction, for each of its
+/// postings that the predicate matches.
+#[derive(Debug, Clone)]
+pub struct AutomatedTransaction {
+    predicate: Query,
+    postings: Vec<AutomatedPosting>,
+}
+
+impl AutomatedTransaction {
+    pub fn new(predicate: Query) -> AutomatedTransaction {
+        AutomatedTransaction {
+            predicate,
+            postings: Vec::new(),
+        }
+    }
+    pub fn predicate(&self) -> &Query {
+        &self.predicate
+    }
+    pub fn postings(&self) -> &Vec<AutomatedPosting> {
+        &self.postings
+    }
+    pub fn add_posting(&mut self, posting: AutomatedPosting) {
+        self.postings.push(posting);
+    }
+    /// The postings generated for one posting of a transaction, if the predicate matches it.
+    /// Generated postings never match, so automated transactions do not feed each other.
+    pub fn generate(&self, journal: &Journal, posting: &Posting) -> Vec<Posting> {
+        let amount = match posting.amount() {
+            Some(amount) if !posting.generated && self.predicate.matches(journal, posting) => amount,
+            _ => return Vec::new(),
+        };
+        self.postings.iter().map(|automated| {
+            let amount = match automated.amount {
+                AutomatedAmount::Multiplier(ref factor) => amount.clone() * factor.clone(),
+                AutomatedAmount::Fixed(ref fixed) => fixed.clone(),
+            };
+            let mut generated = Posting::new(posting.transaction, automated.account, Some(amount));
+            generated.kind = automated.kind;
+            generated.generated = true;
+            generated
+        }).collect()
+    }
+}
+
+/// A posting of an automated transaction, for an amount in a commodity or, for a bare number, for
+/// that multiple of the matched posting's amount.
+#[derive(Debug, Clone)]
+pub struct AutomatedPosting {
+    account: AccountId,
+    kind: PostingKind,
+    amount: AutomatedAmount,
+}
+
+impl AutomatedPosting {
+    pub fn new(account: AccountId, kind: PostingKind, amount: AutomatedAmount) -> AutomatedPosting {
+        AutomatedPosting {
+            account,
+            kind,
+            amount,
+        }
+    }
+    pub fn account(&self) -> AccountId {
+        self.account
+    }
+    pub fn kind(&self) -> PostingKind {
+        self.kind
+    }
+    pub fn amount(&self) -> &AutomatedAmount {
+        &self.amount
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AutomatedAmount {
+    Multiplier(Amount),
+    Fixed(Amount),
+}
+
+/// A block starting `~ PERIOD`, such as a monthly budget, whose postings recur on every date of
+/// the period.
+#[derive(Debug, Clone)]
+pub struct PeriodicTransaction {
+    file: Option<FileId>,
+    line: usize,
+    period: Period,
+    postings: Vec<PeriodicPosting>,
+}
+
+impl PeriodicTransaction {
+    pub fn new(file: Option<FileId>, line: usize, period: Period) -> PeriodicTransaction {
+        PeriodicTransaction {
+            file,
+            line,
+            period,
+            postings: Vec::new(),
+        }
+    }
+    pub fn file(&self) -> Option<FileId> {
+        self.file
+    }
+    /// The line of the file the periodic transaction starts on.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+    pub fn period(&self) -> &Period {
+        &self.period
+    }
+    pub fn postings(&self) -> &Vec<PeriodicPosting> {
+        &self.postings
+    }
+    pub fn add_posting(&mut self, posting: PeriodicPosting) {
+        self.postings.push(posting);
+    }
+    /// The occurrences from `begin` up to but not including `end`. A posting that leaves out its
+    /// amount takes whatever balances the others of its kind, one posting per commodity, with the
+    /// journal's unit conversions applied.
+    pub fn expand(&self, journal: &Journal, begin: NaiveDate, end: NaiveDate) -> Vec<Occurrence> {
+        let mut postings = Vec::new();
+        for periodic in &self.postings {
+            if periodic.amount.is_some() {
+                postings.push(periodic.clone());
+                continue;
+            }
+            let mut sum = Balance::new();
+            for amount in self.postings.iter().filter(|p| p.kind == periodic.kind).filter_map(|p| p.amount.as_ref()) {
+                sum += journal.reduce(amount);
+            }
+            let balancing = (-sum).amounts()
+                .map(|amount| PeriodicPosting::new(periodic.account, periodic.kind, Some(journal.unreduce(amount))))
+                .collect::<Vec<PeriodicPosting>>();
+            if balancing.is_empty() {
+                postings.push(PeriodicPosting::new(periodic.account, periodic.kind, Some(Amount::new(CommodityId::NONE, Quantity::zero()))));
+            }
+            postings.extend(balancing);
+        }
+        self.period.occurrences(begin, end).into_iter()
+            .map(|date| Occurrence {
+                date,
+                postings: postings.clone(),
+            })
+            .collect()
+    }
+}
+
+/// One date on which a periodic transaction recurs. Every one of its postings has an amount.
+#[derive(Debug, Clone)]
+pub struct Occurrence {
+    date: NaiveDate,
+    postings: Vec<PeriodicPosting>,
+}
+
+impl Occurrence {
+    pub fn date(&self) -> NaiveDate {
+        self.date
+    }
+    pub fn postings(&self) -> &Vec<PeriodicPosting> {
+        &self.postings
+    }
+}
+
+/// A posting of a periodic transaction. As in any transaction, one may leave out its amount.
+#[derive(Debug, Clone)]
+pub struct PeriodicPosting {
+    account: AccountId,
+    kind: PostingKind,
+    amount: Option<Amount>,
+}
+
+impl PeriodicPosting {
+    pub fn new(account: AccountId, kind: PostingKind, amount: Option<Amount>) -> PeriodicPosting {
+        PeriodicPosting {
+            account,
+            kind,
+            amount,
+        }
+    }
+    pub fn account(&self) -> AccountId {
+        self.account
+    }
+    pub fn kind(&self) -> PostingKind {
+        self.kind
+    }
+    pub fn amount(&self) -> Option<&Amount> {
+        self.amount.as_ref()
+    }
+}
+
+/// A quantity held exactly, as a fraction, so that `0.10 + 0.20` is `0.30`.
+pub type Quantity = BigRational;
+
+/// Reads a decimal number such as `-1000.50`, along with how many digits follow its point.
+pub fn parse_quantity(text: &str) -> Result<(Quantity, usize), String> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let (integer, fraction) = match digits.find('.') {
+        Some(index) => (&digits[..index], &digits[index + 1..]),
+        None => (digits, ""),
+    };
+    if integer.len() + fraction.len() == 0 || !integer.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return Err(format!("Could not parse {}", text));
+    }
+    let numerator = format!("{}{}", integer, fraction).parse::<BigInt>().map_err(|_| format!("Could not parse {}", text))?;
+    let quantity = Quantity::new(numerator, pow(BigInt::from(10), fraction.len()));
+    Ok((if negative { -quantity } else { quantity }, fraction.len()))
+}
+
+/// Writes a quantity with `precision` digits after the point, rounding halves away from zero.
+/// There is no sign if it rounds to zero.
+pub fn format_quantity(quantity: &Quantity, precision: usize) -> String {
+    let scaled = (quantity * Quantity::from_integer(pow(BigInt::from(10), precision))).round().to_integer();
+    let digits = format!("{:0>width$}", scaled.abs(), width = precision + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - precision);
+    let sign = if scaled.is_negative() { "-" } else { "" };
+    if fraction.is_empty() {
+        format!("{}{}", sign, integer)
+    }
+    else {
+        format!("{}{}.{}", sign, integer, fraction)
+    }
+}
+
+/// A quantity of a commodity, which is empty for a plain number. The precision is how many
+/// digits were written after the decimal point, and is kept through arithmetic: a sum has the
+/// greater precision of the two, and a product both added together.
 #[derive(Debug, Clone)]
 pub struct Amount {
-    commodity: CommoditySymbol,
-    price: f64,
+    commodity: CommodityId,
+    quantity: Quantity,
+    precision: usize,
 }
 
 impl Amount {
-    pub fn new(commodity: CommoditySymbol, price: f64) -> Amount {
+    pub fn new(commodity: CommodityId, quantity: Quantity) -> Amount {
         Amount {
-            commodity: commodity,
-            price: price,
+            commodity,
+            quantity,
+            precision: 0,
+        }
+    }
+    /// An amount of a number such as `-1000.50`, `1,000.50` or `1.000,50`, with the precision it
+    /// is written in.
+    pub fn parse(commodity: CommodityId, number: &str) -> Result<Amount, String> {
+        let (decimal_mark, thousands_separator) = number_marks(number);
+        let number = number.chars()
+            .filter(|&c| Some(c) != thousands_separator)
+            .map(|c| if c == decimal_mark { '.' } else { c })
+            .collect::<String>();
+        let (quantity, precision) = parse_quantity(&number)?;
+        Ok(Amount { commodity, quantity, precision })
+    }
+    pub fn with_commodity(mut self, commodity: CommodityId) -> Amount {
+        self.commodity = commodity;
+        self
+    }
+    pub fn with_precision(mut self, precision: usize) -> Amount {
+        self.precision = precision;
+        self
+    }
+    pub fn commodity(&self) -> CommodityId {
+        self.commodity
+    }
+    /// The symbol of the commodity, which is empty for a plain number.
+    pub fn symbol(&self) -> &'static str {
+        self.commodity.symbol()
+    }
+    pub fn quantity(&self) -> &Quantity {
+        &self.quantity
+    }
+    pub fn precision(&self) -> usize {
+        self.precision
+    }
+    pub fn is_zero(&self) -> bool {
+        self.quantity.is_zero()
+    }
+    pub fn is_negative(&self) -> bool {
+        self.quantity.is_negative()
+    }
+    pub fn abs(&self) -> Amount {
+        Amount { quantity: self.quantity.abs(), ..self.clone() }
+    }
+    /// The amount rounded to `precision` digits after the point, halves away from zero.
+    pub fn round(&self, precision: usize) -> Amount {
+        let scale = Quantity::from_integer(pow(BigInt::from(10), precision));
+        Amount {
+            commodity: self.commodity,
+            quantity: (&self.quantity * &scale).round() / scale,
+            precision,
+        }
+    }
+
+    /// The sum, or an error if both sides have a commodity and they differ. A plain number takes
+    /// on the commodity of the other side.
+    pub fn checked_add(self, other: Amount) -> Result<Amount, String> {
+        Ok(Amount {
+            commodity: self.combined_commodity(&other)?,
+            quantity: self.quantity + other.quantity,
+            precision: self.precision.max(other.precision),
+        })
+    }
+    /// The difference, or an error if both sides have a commodity and they differ.
+    pub fn checked_sub(self, other: Amount) -> Result<Amount, String> {
+        self.checked_add(-other)
+    }
+    /// The product, or an error if both sides have a commodity and they differ.
+    pub fn checked_mul(self, other: Amount) -> Result<Amount, String> {
+        Ok(Amount {
+            commodity: self.combined_commodity(&other)?,
+            quantity: self.quantity * other.quantity,
+            precision: self.precision + other.precision,
+        })
+    }
+
+    /// The quotient, at the precision of the dividend. Dividing two amounts in the same commodity
+    /// gives a plain number, as in `$120 / $3`.
+    pub fn checked_div(self, other: Amount) -> Result<Amount, String> {
+        if other.is_zero() {
+            return Err("Divide by zero".to_string());
+        }
+        let commodity = if self.commodity != CommodityId::NONE && self.commodity == other.commodity {
+            CommodityId::NONE
+        }
+        else {
+            self.combined_commodity(&other)?
+        };
+        Ok(Amount::new(commodity, self.quantity / other.quantity).with_precision(self.precision))
+    }
+
+    // The commodity of a sum or product. A plain number takes on the commodity of the other side.
+    fn combined_commodity(&self, other: &Amount) -> Result<CommodityId, String> {
+        if self.commodity == CommodityId::NONE {
+            Ok(other.commodity)
+        }
+        else if other.commodity == CommodityId::NONE || self.commodity == other.commodity {
+            Ok(self.commodity)
+        }
+        else {
+            Err(format!("Cannot mix commodities {} and {}", self.symbol(), other.symbol()))
         }
     }
 }
 
+/// Amounts are equal when their commodities and quantities are, whatever their precision.
+impl PartialEq for Amount {
+    fn eq(&self, other: &Amount) -> bool {
+        self.commodity == other.commodity && self.quantity == other.quantity
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+    fn neg(self) -> Amount {
+        Amount { quantity: -self.quantity, ..self }
+    }
+}
+
+/// Panics if both sides have a commodity and they differ; `Amount::checked_add` returns an error
+/// instead.
+impl Add for Amount {
+    type Output = Amount;
+    fn add(self, other: Amount) -> Amount {
+        self.checked_add(other).unwrap_or_else(|message| panic!("{}", message))
+    }
+}
+
+/// Panics if both sides have a commodity and they differ; `Amount::checked_sub` returns an error
+/// instead.
+impl Sub for Amount {
+    type Output = Amount;
+    fn sub(self, other: Amount) -> Amount {
+        self.checked_sub(other).unwrap_or_else(|message| panic!("{}", message))
+    }
+}
+
+/// Panics if both sides have a commodity and they differ; `Amount::checked_mul` returns an error
+/// instead.
+impl Mul for Amount {
+    type Output = Amount;
+    fn mul(self, other: Amount) -> Amount {
+        self.checked_mul(other).unwrap_or_else(|message| panic!("{}", message))
+    }
+}
+
 pub type CommoditySymbol = String;
 
+/// Amounts in any number of commodities, such as the total of an account holding dollars,
+/// euros and shares. There is at most one amount per commodity, none of them zero, and they are
+/// ordered by commodity symbol. An empty balance is zero.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Balance {
+    amounts: BTreeMap<CommodityId, Amount>,
+}
+
+impl Balance {
+    pub fn new() -> Balance {
+        Balance::default()
+    }
+    pub fn amounts(&self) -> impl Iterator<Item = &Amount> {
+        let mut amounts = self.amounts.values().collect::<Vec<&Amount>>();
+        amounts.sort_by_key(|amount| amount.symbol());
+        amounts.into_iter()
+    }
+    pub fn amount(&self, commodity: &str) -> Option<&Amount> {
+        self.amounts.get(&CommodityId::find(commodity)?)
+    }
+    pub fn len(&self) -> usize {
+        self.amounts.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.amounts.is_empty()
+    }
+    /// Each amount rounded to the precision `precision` gives for its commodity, leaving out
+    /// those that round to zero.
+    pub fn round<F: Fn(CommodityId) -> usize>(&self, precision: F) -> Balance {
+        self.amounts.values()
+            .map(|amount| amount.round(precision(amount.commodity)))
+            .fold(Balance::new(), |balance, amount| balance + amount)
+    }
+}
+
+impl From<Amount> for Balance {
+    fn from(amount: Amount) -> Balance {
+        Balance::new() + amount
+    }
+}
+
+impl AddAssign<Amount> for Balance {
+    fn add_assign(&mut self, amount: Amount) {
+        let sum = match self.amounts.remove(&amount.commodity) {
+            // Both are in the commodity the balance keys them by, so this cannot panic.
+            Some(total) => total + amount,
+            None => amount,
+        };
+        if !sum.is_zero() {
+            self.amounts.insert(sum.commodity, sum);
+        }
+    }
+}
+
+impl AddAssign<Balance> for Balance {
+    fn add_assign(&mut self, balance: Balance) {
+        for amount in balance.amounts.into_values() {
+            *self += amount;
+        }
+    }
+}
+
+impl SubAssign<Amount> for Balance {
+    fn sub_assign(&mut self, amount: Amount) {
+        *self += -amount;
+    }
+}
+
+impl SubAssign<Balance> for Balance {
+    fn sub_assign(&mut self, balance: Balance) {
+        *self += -balance;
+    }
+}
+
+impl Add<Amount> for Balance {
+    type Output = Balance;
+    fn add(mut self, amount: Amount) -> Balance {
+        self += amount;
+        self
+    }
+}
+
+impl Add<Balance> for Balance {
+    type Output = Balance;
+    fn add(mut self, balance: Balance) -> Balance {
+        self += balance;
+        self
+    }
+}
+
+impl Sub<Amount> for Balance {
+    type Output = Balance;
+    fn sub(mut self, amount: Amount) -> Balance {
+        self -= amount;
+        self
+    }
+}
+
+impl Sub<Balance> for Balance {
+    type Output = Balance;
+    fn sub(mut self, balance: Balance) -> Balance {
+        self -= balance;
+        self
+    }
+}
+
+impl Neg for Balance {
+    type Output = Balance;
+    fn neg(self) -> Balance {
+        Balance { amounts: self.amounts.into_iter().map(|(commodity, amount)| (commodity, -amount)).collect() }
+    }
+}
+
+/// Multiplies every amount by a plain number. Panics if the factor has a commodity.
+impl Mul<Amount> for Balance {
+    type Output = Balance;
+    fn mul(self, factor: Amount) -> Balance {
+        assert!(factor.commodity == CommodityId::NONE, "Cannot multiply a balance by {}", factor.symbol());
+        let amounts = self.amounts.into_iter()
+            .map(|(commodity, amount)| (commodity, amount * factor.clone()))
+            .filter(|(_, amount)| !amount.is_zero())
+            .collect();
+        Balance { amounts }
+    }
+}
+
+/// The price of one unit of a commodity on a date, from a `P` directive or a posting's cost.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Price {
+    date: NaiveDate,
+    commodity: CommodityId,
+    price: Amount,
+}
+
+impl Price {
+    pub fn new(date: NaiveDate, commodity: CommodityId, price: Amount) -> Price {
+        Price {
+            date,
+            commodity,
+            price,
+        }
+    }
+    pub fn date(&self) -> NaiveDate {
+        self.date
+    }
+    pub fn commodity(&self) -> CommodityId {
+        self.commodity
+    }
+    pub fn price(&self) -> &Amount {
+        &self.price
+    }
+}
+
+/// Every price seen, kept per pair of commodities in date order. A second price for the same
+/// pair on the same date replaces the first.
+#[derive(Debug, Clone, Default)]
+pub struct PriceHistory {
+    prices: BTreeMap<(CommodityId, CommodityId), BTreeMap<NaiveDate, Amount>>,
+}
+
+impl PriceHistory {
+    pub fn new() -> PriceHistory {
+        PriceHistory::default()
+    }
+    pub fn add(&mut self, price: Price) {
+        let pair = (price.commodity, price.price.commodity);
+        self.prices.entry(pair).or_default().insert(price.date, price.price);
+    }
+    pub fn is_empty(&self) -> bool {
+        self.prices.is_empty()
+    }
+    /// All prices, ordered by commodity, then the commodity they are quoted in, then date.
+    pub fn iter(&self) -> impl Iterator<Item = Price> + '_ {
+        let mut pairs = self.prices.iter().collect::<Vec<_>>();
+        pairs.sort_by_key(|((commodity, target), _)| (commodity.symbol(), target.symbol()));
+        pairs.into_iter().flat_map(|(&(commodity, _), dates)| {
+            dates.iter().map(move |(date, price)| Price::new(*date, commodity, price.clone()))
+        })
+    }
+    /// The most recent price of `commodity` in `target` on or before `date`.
+    pub fn price(&self, commodity: &str, target: &str, date: NaiveDate) -> Option<Price> {
+        self.price_of(CommodityId::find(commodity)?, CommodityId::find(target)?, date)
+    }
+    fn price_of(&self, commodity: CommodityId, target: CommodityId, date: NaiveDate) -> Option<Price> {
+        let dates = self.prices.get(&(commodity, target))?;
+        dates.range(..=date).next_back()
+            .map(|(date, price)| Price::new(*date, commodity, price.clone()))
+    }
+    /// How many units of `target` one unit of `commodity` is worth on `date`, following a chain
+    /// of prices if no price connects them directly. A price of B in A counts as a price of A in
+    /// B too. Of all the chains, the one whose oldest price is the most recent is used, and of
+    /// those the shortest.
+    pub fn rate(&self, commodity: &str, target: &str, date: NaiveDate) -> Option<Quantity> {
+        if commodity == target {
+            return Some(Quantity::one());
+        }
+        let (commodity, target) = (CommodityId::find(commodity)?, CommodityId::find(target)?);
+        let mut edges: BTreeMap<CommodityId, Vec<(CommodityId, Quantity, NaiveDate)>> = BTreeMap::new();
+        for ((from, to), dates) in &self.prices {
+            if let Some((day, price)) = dates.range(..=date).next_back() {
+                edges.entry(*from).or_default().push((*to, price.quantity.clone(), *day));
+                if !price.is_zero() {
+                    edges.entry(*to).or_default().push((*from, price.quantity.recip(), *day));
+                }
+            }
+        }
+
+        // Dijkstra's algorithm, where a chain is as fresh as its oldest price.
+        let mut best: BTreeMap<CommodityId, (NaiveDate, usize, Quantity)> = BTreeMap::new();
+        let mut queue = BinaryHeap::new();
+        best.insert(commodity, (NaiveDate::MAX, 0, Quantity::one()));
+        queue.push((NaiveDate::MAX, Reverse(0), commodity));
+        while let Some((freshness, Reverse(hops), node)) = queue.pop() {
+            let (best_freshness, best_hops, ref rate) = best[&node];
+            if (best_freshness, best_hops) != (freshness, hops) {
+                continue;
+            }
+            if node == target {
+                return Some(rate.clone());
+            }
+            let rate = rate.clone();
+            for (next, price, day) in edges.get(&node).into_iter().flatten() {
+                let (next, day) = (*next, *day);
+                let candidate = (freshness.min(day), hops + 1, &rate * price);
+                let better = match best.get(&next) {
+                    Some(&(f, h, _)) => candidate.0 > f || (candidate.0 == f && candidate.1 < h),
+                    None => true,
+                };
+                if better {
+                    queue.push((candidate.0, Reverse(candidate.1), next));
+                    best.insert(next, candidate);
+                }
+            }
+        }
+        None
+    }
+    /// The most recent price of `commodity` on or before `date`, in whichever commodity it was
+    /// quoted in.
+    pub fn latest(&self, commodity: &str, date: NaiveDate) -> Option<Price> {
+        let commodity = CommodityId::find(commodity)?;
+        self.prices.keys()
+            .filter(|pair| pair.0 == commodity)
+            .filter_map(|pair| self.price_of(pair.0, pair.1, date))
+            .max_by_key(|price| price.date)
+    }
+}
+
+/// Converts an amount into `target` at the prices known on `date`, through other commodities if
+/// need be. `None` if no chain of prices connects the two.
+pub fn convert(prices: &PriceHistory, amount: &Amount, target: &str, date: NaiveDate) -> Option<Amount> {
+    let rate = prices.rate(amount.symbol(), target, date)?;
+    // The target has a rate, so it is the amount's own commodity or one with prices, and is
+    // already interned.
+    Some(Amount { commodity: CommodityId::find(target)?, quantity: &amount.quantity * rate, precision: amount.precision })
+}
+
+/// A commodity declared with the `commodity` directive.
+#[derive(Debug, Clone)]
+pub struct Commodity {
+    id: CommodityId,
+    note: Option<String>,
+    style: Option<CommodityStyle>,
+    nomarket: bool,
+    default: bool,
+}
+
+impl Commodity {
+    pub fn new(id: CommodityId) -> Commodity {
+        Commodity {
+            id,
+            note: None,
+            style: None,
+            nomarket: false,
+            default: false,
+        }
+    }
+    pub fn id(&self) -> CommodityId {
+        self.id
+    }
+    pub fn symbol(&self) -> &'static str {
+        self.id.symbol()
+    }
+    pub fn note(&self) -> Option<&String> {
+        self.note.as_ref()
+    }
+    pub fn add_note(&mut self, note: &str) {
+        add_note(&mut self.note, note);
+    }
+    pub fn set_style(&mut self, style: CommodityStyle) {
+        self.style = Some(style);
+    }
+    /// Whether market prices for this commodity should not be looked up.
+    pub fn is_nomarket(&self) -> bool {
+        self.nomarket
+    }
+    pub fn set_nomarket(&mut self, nomarket: bool) {
+        self.nomarket = nomarket;
+    }
+    /// Whether this is the commodity of amounts written as bare numbers.
+    pub fn is_default(&self) -> bool {
+        self.default
+    }
+    pub fn set_default(&mut self, default: bool) {
+        self.default = default;
+    }
+}
+
+/// How amounts in a commodity are written. `$1,000.00` has the symbol before the number with
+/// nothing between them, a comma between thousands, a period for the decimal mark, and two
+/// decimal places.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommodityStyle {
+    prefix: bool,
+    separated: bool,
+    decimal_mark: char,
+    thousands_separator: Option<char>,
+    precision: usize,
+}
+
+impl CommodityStyle {
+    pub fn new(prefix: bool, separated: bool, decimal_mark: char, thousands_separator: Option<char>, precision: usize) -> CommodityStyle {
+        CommodityStyle {
+            prefix,
+            separated,
+            decimal_mark,
+            thousands_separator,
+            precision,
+        }
+    }
+
+    /// A symbol such as `$` goes before the number, and a name such as `EUR` after it.
+    pub fn default_for(symbol: &str) -> CommodityStyle {
+        let prefix = symbol.chars().count() == 1 && !symbol.chars().all(char::is_alphabetic);
+        CommodityStyle::new(prefix, !prefix, '.', None, 2)
+    }
+
+    /// Reads the style from a sample amount such as `$1,000.00` or `1.000,00 EUR`, returning
+    /// the commodity along with it.
+    pub fn parse(sample: &str) -> Result<(CommoditySymbol, CommodityStyle), String> {
+        let sample = sample.trim();
+        let is_number = |c: char| c.is_ascii_digit() || c == '.' || c == ',';
+        let start = sample.find(|c: char| c.is_ascii_digit()).ok_or_else(|| format!("No number in {}", sample))?;
+        let end = start + sample[start..].find(|c: char| !is_number(c)).unwrap_or(sample.len() - start);
+        let before = sample[..start].trim_matches('-');
+        let after = &sample[end..];
+        let (symbol, prefix, separated) = if before.trim().is_empty() {
+            (after.trim(), false, after.starts_with(char::is_whitespace))
+        }
+        else {
+            (before.trim(), true, before.ends_with(char::is_whitespace))
+        };
+        if !before.trim().is_empty() && !after.trim().is_empty() {
+            return Err(format!("Commodity on both sides of {}", sample));
+        }
+
+        let number = &sample[start..end];
+        let (decimal_mark, thousands_separator) = number_marks(number);
+        let precision = match number.rfind(decimal_mark) {
+            Some(index) => number.len() - index - 1,
+            None => 0,
+        };
+        Ok((symbol.to_string(), CommodityStyle::new(prefix, separated, decimal_mark, thousands_separator, precision)))
+    }
+
+    pub fn is_prefix(&self) -> bool {
+        self.prefix
+    }
+    pub fn is_separated(&self) -> bool {
+        self.separated
+    }
+    pub fn decimal_mark(&self) -> char {
+        self.decimal_mark
+    }
+    pub fn thousands_separator(&self) -> Option<char> {
+        self.thousands_separator
+    }
+    pub fn precision(&self) -> usize {
+        self.precision
+    }
+
+    // Takes in another amount written in the same commodity. Where the symbol goes is settled by
+    // the first; its marks stand unless it had neither, and the precision is the greatest seen.
+    fn learn(&mut self, other: &CommodityStyle) {
+        if self.precision == 0 && self.thousands_separator.is_none() {
+            self.decimal_mark = other.decimal_mark;
+            self.thousands_separator = other.thousands_separator;
+        }
+        else if self.thousands_separator.is_none() && other.thousands_separator != Some(self.decimal_mark) {
+            self.thousands_separator = other.thousands_separator;
+        }
+        self.precision = self.precision.max(other.precision);
+    }
+
+    /// Writes a quantity in this style, rounded to its precision, with the sign after a prefix
+    /// symbol as in `$-40.00`.
+    pub fn format(&self, symbol: &str, quantity: &Quantity) -> String {
+        let digits = format_quantity(quantity, self.precision);
+        let (sign, digits) = match digits.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", &digits[..]),
+        };
+        let (integer, fraction) = match digits.find('.') {
+            Some(index) => (&digits[..index], &digits[index + 1..]),
+            None => (digits, ""),
+        };
+        let mut number = sign.to_string();
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                if let Some(separator) = self.thousands_separator {
+                    number.push(separator);
+                }
+            }
+            number.push(digit);
+        }
+        if !fraction.is_empty() {
+            number.push(self.decimal_mark);
+            number.push_str(fraction);
+        }
+
+        let space = if self.separated && !symbol.is_empty() { " " } else { "" };
+        if self.prefix {
+            format!("{}{}{}", symbol, space, number)
+        }
+        else {
+            format!("{}{}{}", number, space, symbol)
+        }
+    }
+}
+
+// The decimal mark and thousands separator of a number such as `1,000.00` or `1.000,00`. When
+// only one mark appears, it separates thousands if it appears more than once or, for a comma,
+// is followed by exactly three digits.
+fn number_marks(number: &str) -> (char, Option<char>) {
+    let last = number.rfind(['.', ',']);
+    match last {
+        None => ('.', None),
+        Some(index) => {
+            let mark = number[index..].chars().next().unwrap();
+            let other = if mark == '.' { ',' } else { '.' };
+            if number.contains(other) {
+                (mark, Some(other))
+            }
+            else if number.matches(mark).count() > 1 || (mark == ',' && number.len() - index - 1 == 3) {
+                (other, Some(mark))
+            }
+            else {
+                (mark, None)
+            }
+        }
+    }
+}
+
+pub type TagName = String;
+
+/// Tags without a value come from `; :tag:` notes; `; Key: value` notes give a string, and
+/// `; Key:: value` an evaluated date or amount.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagValue {
+    String(String),
+    Date(NaiveDate),
+    Amount(Amount),
+}
+
+impl fmt::Display for TagValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TagValue::String(ref s) => write!(f, "{}", s),
+            TagValue::Date(ref date) => write!(f, "{}", date.format("%Y-%m-%d")),
+            TagValue::Amount(ref amount) => write!(f, "{}{}", amount.symbol(), format_quantity(amount.quantity(), amount.precision())),
+        }
+    }
+}
+
+pub type Tags = BTreeMap<TagName, Option<TagValue>>;
+
+/// A payee declared with `payee`, and the patterns of descriptions that stand for it.
+#[derive(Debug, Clone)]
+pub struct Payee {
+    name: String,
+    aliases: Vec<String>,
+}
+
+impl Payee {
+    pub fn new(name: String) -> Payee {
+        Payee {
+            name,
+            aliases: Vec::new(),
+        }
+    }
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+    pub fn aliases(&self) -> &Vec<String> {
+        &self.aliases
+    }
+    pub fn add_alias(&mut self, alias: String) {
+        self.aliases.push(alias);
+    }
+}
+
+/// A tag declared with `tag`, and the value expressions its values must satisfy.
+#[derive(Debug, Clone)]
+pub struct Tag {
+    name: TagName,
+    checks: Vec<String>,
+    assertions: Vec<String>,
+}
+
+impl Tag {
+    pub fn new(name: TagName) -> Tag {
+        Tag {
+            name,
+            checks: Vec::new(),
+            assertions: Vec::new(),
+        }
+    }
+    pub fn name(&self) -> &TagName {
+        &self.name
+    }
+    pub fn checks(&self) -> &Vec<String> {
+        &self.checks
+    }
+    pub fn add_check(&mut self, check: String) {
+        self.checks.push(check);
+    }
+    pub fn assertions(&self) -> &Vec<String> {
+        &self.assertions
+    }
+    pub fn add_assertion(&mut self, assertion: String) {
+        self.assertions.push(assertion);
+    }
+}
+
+fn add_note(existing: &mut Option<String>, note: &str) {
+    match *existing {
+        Some(ref mut existing) => {
+            existing.push('\n');
+            existing.push_str(note);
+        }
+        None => *existing = Some(note.to_string()),
+    }
+}
+
 impl Account {
     pub fn new(s: String) -> Account {
         Account {
             name: s,
+            declared: false,
+            note: None,
+            aliases: Vec::new(),
+            payees: Vec::new(),
+            checks: Vec::new(),
+            assertions: Vec::new(),
+            default: false,
+            postings: Vec::new(),
         }
     }
     pub fn name(&self) -> &String {
         &self.name
     }
+    pub fn is_declared(&self) -> bool {
+        self.declared
+    }
+    pub fn note(&self) -> Option<&String> {
+        self.note.as_ref()
+    }
+    pub fn add_note(&mut self, note: &str) {
+        add_note(&mut self.note, note);
+    }
+    /// Other names a posting may use for this account.
+    pub fn aliases(&self) -> &Vec<AccountName> {
+        &self.aliases
+    }
+    pub fn add_alias(&mut self, alias: AccountName) {
+        self.aliases.push(alias);
+    }
+    /// Patterns for payees whose postings to an `Unknown` account belong to this account.
+    pub fn payees(&self) -> &Vec<String> {
+        &self.payees
+    }
+    pub fn add_payee(&mut self, payee: String) {
+        self.payees.push(payee);
+    }
+    pub fn checks(&self) -> &Vec<String> {
+        &self.checks
+    }
+    pub fn add_check(&mut self, check: String) {
+        self.checks.push(check);
+    }
+    pub fn assertions(&self) -> &Vec<String> {
+        &self.assertions
+    }
+    pub fn add_assertion(&mut self, assertion: String) {
+        self.assertions.push(assertion);
+    }
+    /// Whether this is the default account, which balances transactions that have a single
+    /// posting.
+    pub fn is_default(&self) -> bool {
+        self.default
+    }
+    pub fn set_default(&mut self, default: bool) {
+        self.default = default;
+    }
+    /// The postings to the account, in the order they were written.
+    pub fn postings(&self) -> &Vec<PostingId> {
+        &self.postings
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Transaction {
-    id: usize,
-    date: Date<Utc>,
-    edate: Option<Date<Utc>>,
+    file: Option<FileId>,
+    line: usize,
+    date: NaiveDate,
+    edate: Option<NaiveDate>,
     status: Status,
+    code: Option<String>,
     description: String,
+    payee: String,
+    note: Option<String>,
+    tags: Tags,
+    postings: Vec<PostingId>,
 }
 
 impl Transaction {
-    pub fn new(id: usize, date: Date<Utc>, status: Status, desc: String) -> Transaction {
+    pub fn new(file: Option<FileId>, line: usize, date: NaiveDate, edate: Option<NaiveDate>, status: Status, code: Option<String>, desc: String) -> Transaction {
         Transaction {
-            id: id,
-            date: date,
-            edate: None,
-            status: status,
+            file,
+            line,
+            date,
+            edate,
+            status,
+            code,
+            payee: desc.clone(),
             description: desc,
+            note: None,
+            tags: Tags::new(),
+            postings: Vec::new(),
         }
     }
+    /// The file the transaction was read from, which `Journal::file` gives the path of.
+    pub fn file(&self) -> Option<FileId> {
+        self.file
+    }
+    /// The line of the file the transaction starts on.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+    pub fn date(&self) -> NaiveDate {
+        self.date
+    }
+    pub fn edate(&self) -> Option<NaiveDate> {
+        self.edate
+    }
+    pub fn status(&self) -> Status {
+        self.status
+    }
+    pub fn code(&self) -> Option<&String> {
+        self.code.as_ref()
+    }
+    pub fn description(&self) -> &String {
+        &self.description
+    }
+    /// The payee, which is the description unless it matched the alias of a declared payee.
+    pub fn payee(&self) -> &String {
+        &self.payee
+    }
+    pub fn set_payee(&mut self, payee: String) {
+        self.payee = payee;
+    }
+    pub fn note(&self) -> Option<&String> {
+        self.note.as_ref()
+    }
+    pub fn add_note(&mut self, note: &str) {
+        add_note(&mut self.note, note);
+    }
+    pub fn tags(&self) -> &Tags {
+        &self.tags
+    }
+    pub fn set_tag(&mut self, name: TagName, value: Option<TagValue>) {
+        self.tags.insert(name, value);
+    }
+    /// The postings of the transaction, in the order they were written.
+    pub fn postings(&self) -> &Vec<PostingId> {
+        &self.postings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::lex;
+    use parser::parse;
+    use std::env;
+    use std::fs;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn amount(number: &str, commodity: &str) -> Amount {
+        Amount::parse(CommodityId::intern(commodity), number).unwrap()
+    }
+
+    #[test]
+    fn test_price_history() {
+        let mut prices = PriceHistory::new();
+        prices.add(Price::new(date(2013, 1, 31), CommodityId::intern("AAPL"), amount("456.78", "$")));
+        prices.add(Price::new(date(2013, 2, 28), CommodityId::intern("AAPL"), amount("441", "$")));
+        prices.add(Price::new(date(2013, 2, 28), CommodityId::intern("AAPL"), amount("442", "$")));
+
+        assert!(prices.price("AAPL", "$", date(2013, 1, 30)).is_none());
+        assert_eq!(prices.price("AAPL", "$", date(2013, 2, 27)).unwrap().price(), &amount("456.78", "$"));
+        let latest = prices.price("AAPL", "$", date(2013, 3, 1)).unwrap();
+        assert_eq!(latest.date(), date(2013, 2, 28));
+        assert_eq!(latest.price(), &amount("442", "$"));
+        assert!(prices.price("AAPL", "EUR", date(2013, 3, 1)).is_none());
+        assert_eq!(prices.iter().count(), 2);
+    }
+
+    #[test]
+    fn test_market_value() {
+        let mut journal = Journal::new();
+        journal.add_price(Price::new(date(2013, 1, 31), CommodityId::intern("AAPL"), amount("450", "$")));
+        journal.add_price(Price::new(date(2013, 2, 15), CommodityId::intern("AAPL"), amount("330", "EUR")));
+        let shares = amount("2", "AAPL");
+
+        assert_eq!(journal.market_value(&shares, Some("$"), date(2013, 3, 1)), amount("900", "$"));
+        assert_eq!(journal.market_value(&shares, None, date(2013, 3, 1)), amount("660", "EUR"));
+        assert_eq!(journal.market_value(&shares, None, date(2013, 2, 1)), amount("900", "$"));
+        assert_eq!(journal.market_value(&shares, Some("$"), date(2013, 1, 1)), shares);
+        assert_eq!(journal.market_value(&shares, Some("AAPL"), date(2013, 3, 1)), shares);
+    }
+
+    #[test]
+    fn test_conversions() {
+        let mut journal = Journal::new();
+        journal.add_conversion(&amount("1", "h"), &amount("60", "m")).unwrap();
+        journal.add_conversion(&amount("1", "m"), &amount("60", "s")).unwrap();
+        journal.add_conversion(&amount("1", "Kb"), &amount("1024", "bytes")).unwrap();
+        assert!(journal.add_conversion(&amount("1", "s"), &amount("0.001", "h")).is_err());
+
+        assert_eq!(journal.reduce(&amount("1.5", "h")), amount("5400", "s"));
+        assert_eq!(journal.unreduce(&amount("5400", "s")), amount("1.5", "h"));
+        assert_eq!(journal.unreduce(&amount("30", "m")), amount("30", "m"));
+        assert_eq!(journal.add_amounts(&amount("1", "h"), &amount("30", "m")), Some(amount("1.5", "h")));
+        assert_eq!(journal.add_amounts(&amount("1", "h"), &amount("-75", "m")), Some(amount("-15", "m")));
+        assert_eq!(journal.add_amounts(&amount("512", "bytes"), &amount("0.5", "Kb")), Some(amount("1", "Kb")));
+        assert!(journal.add_amounts(&amount("1", "h"), &amount("1", "Kb")).is_none());
+    }
+
+    #[test]
+    fn test_unreduce_picks_largest_unit() {
+        let mut journal = Journal::new();
+        journal.add_conversion(&amount("1", "d"), &amount("1440", "m")).unwrap();
+        journal.add_conversion(&amount("1", "h"), &amount("60", "m")).unwrap();
+        assert_eq!(journal.unreduce(&amount("90", "m")), amount("1.5", "h"));
+        assert_eq!(journal.unreduce(&amount("2160", "m")), amount("1.5", "d"));
+        assert_eq!(journal.unreduce(&amount("-30", "m")), amount("-30", "m"));
+    }
+
+    #[test]
+    fn test_exact_arithmetic() {
+        assert_eq!(amount("0.10", "$") + amount("0.20", "$"), amount("0.30", "$"));
+        assert!((amount("0.10", "$") + amount("0.20", "$") - amount("0.3", "$")).is_zero());
+        assert_eq!((amount("1.5", "$") + amount("2.25", "$")).precision(), 2);
+        assert_eq!(amount("100", "$") * amount("0.0725", ""), amount("7.25", "$"));
+        assert_eq!(amount("1.5", "") * amount("2.25", "EUR"), amount("3.375", "EUR"));
+        assert_eq!((amount("1.5", "") * amount("2.25", "EUR")).precision(), 3);
+        assert_eq!((-amount("10.50", "$")).precision(), 2);
+        assert_eq!(amount("3", "") - amount("5", "h"), amount("-2", "h"));
+        assert_eq!(amount("1", "$").checked_sub(amount("3", "")), Ok(amount("-2", "$")));
+        assert_eq!(amount("1", "$").checked_add(amount("1", "EUR")), Err("Cannot mix commodities $ and EUR".to_string()));
+        assert!(amount("2", "h").checked_mul(amount("3", "$")).is_err());
+
+        let (third, precision) = parse_quantity("1").unwrap();
+        let third = third / Quantity::from_integer(BigInt::from(3));
+        assert_eq!(precision, 0);
+        assert_eq!(format_quantity(&third, 4), "0.3333");
+        assert_eq!(format_quantity(&-third, 0), "0");
+        assert_eq!(format_quantity(&parse_quantity("-0.005").unwrap().0, 2), "-0.01");
+        assert!(parse_quantity("1.2.3").is_err());
+        assert!(parse_quantity("-").is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_adding_commodities_panics() {
+        let _ = amount("1", "$") + amount("1", "EUR");
+    }
+
+    #[test]
+    fn test_journal_links_by_id() {
+        let mut journal = Journal::new();
+        let mut transaction = Transaction::new(None, 1, date(2013, 1, 4), None, Status::Unmarked, None, "Lunch".to_string());
+        transaction.set_tag("work".to_string(), None);
+        let lunch = journal.add_transaction(transaction);
+        let food = journal.add_account("Expenses:Food");
+        let cash = journal.add_account("Assets:Cash");
+        let mut posting = Posting::new(lunch, food, Some(amount("10", "$")));
+        posting.set_tag("work".to_string(), Some(TagValue::String("client".to_string())));
+        let first = journal.add_posting(posting);
+        let second = journal.add_posting(Posting::new(lunch, cash, Some(amount("-10", "$"))));
+
+        assert_eq!(journal.transaction(lunch).postings(), &vec![first, second]);
+        assert_eq!(journal.posting(second).transaction(), lunch);
+        assert_eq!(journal.account(journal.posting(second).account()).name(), "Assets:Cash");
+        assert_eq!(journal.posting(first).tags().get("work"), Some(&Some(TagValue::String("client".to_string()))));
+        assert_eq!(journal.posting(second).tags().get("work"), Some(&None));
+
+        assert_eq!(journal.add_account("Expenses:Food"), food);
+        assert_eq!(journal.declare_account(Account::new("Expenses:Food".to_string())), food);
+        assert!(journal.account(food).is_declared());
+        assert_eq!(journal.accounts().map(|account| account.name().as_str()).collect::<Vec<&str>>(), vec!["Assets:Cash", "Expenses:Food"]);
+        assert!(journal.find_account("Income").is_none());
+    }
+
+    #[test]
+    fn test_commodities_and_files_are_interned() {
+        let dollar = CommodityId::intern("$");
+        assert_eq!(CommodityId::intern("$"), dollar);
+        assert_eq!(amount("10", "$").commodity(), dollar);
+        assert_eq!(dollar.symbol(), "$");
+        assert_eq!(CommodityId::intern(""), CommodityId::NONE);
+
+        let mut journal = Journal::new();
+        assert!(journal.commodity(dollar).is_none());
+        assert!(journal.find_commodity("$").is_none());
+        assert_eq!(journal.declare_commodity(Commodity::new(dollar)), dollar);
+        assert_eq!(journal.find_commodity("$"), Some(dollar));
+
+        // Looking up a symbol never seen does not intern it.
+        assert!(journal.find_commodity("XYZZY").is_none());
+        assert!(journal.prices().price("XYZZY", "$", NaiveDate::MAX).is_none());
+        assert_eq!(journal.market_value(&amount("1", "$"), Some("XYZZY"), NaiveDate::MAX), amount("1", "$"));
+        assert!(CommodityId::find("XYZZY").is_none());
+
+        let file = journal.add_file(Path::new("2013.journal"));
+        assert_eq!(journal.add_file(Path::new("2013.journal")), file);
+        assert_ne!(journal.add_file(Path::new("2014.journal")), file);
+        assert_eq!(journal.file(file), Path::new("2013.journal"));
+    }
+
+    #[test]
+    fn test_journal_queries() {
+        let path = env::temp_dir().join(format!("ledger-queries-{}.journal", ::std::process::id()));
+        fs::write(&path, "2013/01/01 Opening\n  Assets:Cash  $100\n  Assets:Cash Box  $7\n  Equity:Opening Balance\n\n2013/01/04 Lunch\n  Expenses:Food  $10\n  Assets:Cash:Wallet\n\n2013/02/01 Lunch\n  Expenses:Food  $12\n  Assets:Cash\n").unwrap();
+        let journal = Journal::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let food = journal.find_account("Expenses:Food").unwrap();
+        let amounts = journal.account_postings(food).map(|posting| posting.amount().unwrap().clone()).collect::<Vec<Amount>>();
+        assert_eq!(amounts, vec![amount("10", "$"), amount("12", "$")]);
+
+        let january = journal.transactions_between(date(2013, 1, 1), date(2013, 2, 1)).map(|t| t.payee().as_str()).collect::<Vec<&str>>();
+        assert_eq!(january, vec!["Opening", "Lunch"]);
+
+        let cash = journal.find_account("Assets:Cash").unwrap();
+        assert_eq!(journal.balance_as_of(cash, date(2013, 1, 4)), Balance::from(amount("90", "$")));
+        assert_eq!(journal.balance_as_of(cash, date(2013, 12, 31)), Balance::from(amount("78", "$")));
+        assert!(journal.balance_as_of(food, date(2012, 12, 31)).is_empty());
+
+        assert_eq!(journal.payees().collect::<Vec<&String>>(), vec!["Lunch", "Opening"]);
+        assert!(Journal::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_journal_can_be_shared() {
+        let journal = Arc::new(parse(lex("2013/01/04 Lunch\n  Expenses:Food  $10\n  Assets:Cash\n\n2013/01/05 Tea\n  Expenses:Food  $2\n  Assets:Cash\n")));
+        let threads = (0..2).map(|_| {
+            let journal = Arc::clone(&journal);
+            thread::spawn(move || {
+                let cash = journal.find_account("Assets:Cash").unwrap();
+                journal.balance_as_of(cash, date(2013, 12, 31))
+            })
+        }).collect::<Vec<thread::JoinHandle<Balance>>>();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), Balance::from(amount("-12", "$")));
+        }
+    }
+
+    #[test]
+    fn test_learned_styles() {
+        let mut journal = Journal::new();
+        for sample in &["$10", "-$1,000.5", "$0.25"] {
+            let (symbol, style) = CommodityStyle::parse(sample).unwrap();
+            journal.learn_style(CommodityId::intern(&symbol), style);
+        }
+        for sample in &["3 EUR", "1.000,50 EUR", "2.5 EUR"] {
+            let (symbol, style) = CommodityStyle::parse(sample).unwrap();
+            journal.learn_style(CommodityId::intern(&symbol), style);
+        }
+        assert_eq!(journal.style(CommodityId::intern("$")), CommodityStyle::new(true, false, '.', Some(','), 2));
+        assert_eq!(journal.style(CommodityId::intern("EUR")), CommodityStyle::new(false, true, ',', Some('.'), 2));
+        assert_eq!(journal.format_amount(&amount("-1234.5", "$")), "$-1,234.50");
+
+        let euros = Amount::parse(CommodityId::intern("EUR"), "1.000,5").unwrap();
+        assert_eq!(euros, amount("1000.5", "EUR"));
+        assert_eq!(journal.format_amount(&euros), "1.000,50 EUR");
+        assert_eq!(journal.format_amount(&(euros * amount("2", ""))), "2.001,00 EUR");
+    }
+
+    #[test]
+    fn test_balance() {
+        let mut balance = Balance::from(amount("10", "EUR")) + amount("100.00", "$") + amount("5", "AAPL");
+        balance -= amount("10", "EUR");
+        assert_eq!(balance.len(), 2);
+        assert_eq!(balance.amounts().map(|a| a.symbol()).collect::<Vec<&str>>(), vec!["$", "AAPL"]);
+        assert_eq!(balance.amount("$"), Some(&amount("100", "$")));
+        assert!(balance.amount("EUR").is_none());
+
+        let doubled = balance.clone() * amount("2", "");
+        assert_eq!(doubled.amount("AAPL"), Some(&amount("10", "AAPL")));
+        assert!((doubled - balance.clone() - balance.clone()).is_empty());
+        assert!((balance.clone() + -balance.clone()).is_empty());
+
+        let rounded = (Balance::from(amount("0.004", "$")) + amount("1.235", "EUR")).round(|_| 2);
+        assert_eq!(rounded, Balance::from(amount("1.24", "EUR")));
+        let journal = Journal::new();
+        assert_eq!(journal.format_balance(&Balance::new()), vec!["0"]);
+        assert_eq!(journal.format_balance(&Balance::from(amount("-0.004", "$"))), vec!["0"]);
+        assert_eq!(journal.format_balance(&balance), vec!["$100.00", "5.00 AAPL"]);
+    }
+
+    #[test]
+    fn test_convert_through_price_graph() {
+        let mut prices = PriceHistory::new();
+        let mut add = |day, commodity: &str, price, target: &str| {
+            prices.add(Price::new(date(2013, 1, day), CommodityId::intern(commodity), amount(price, target)));
+        };
+        add(10, "EUR", "1.25", "USD");
+        add(10, "USD", "100", "JPY");
+        add(1, "EUR", "200", "JPY");
+        add(5, "GBP", "1.5", "USD");
+        let euros = amount("2", "EUR");
+
+        // The chain through USD is fresher than the direct quote.
+        assert_eq!(convert(&prices, &euros, "JPY", date(2013, 1, 31)), Some(amount("250", "JPY")));
+        assert_eq!(convert(&prices, &euros, "JPY", date(2013, 1, 9)), Some(amount("400", "JPY")));
+        // Reverse rates, and both directions along a chain.
+        assert_eq!(convert(&prices, &amount("125", "JPY"), "EUR", date(2013, 1, 31)),
+                   Some(amount("1", "EUR")));
+        assert_eq!(convert(&prices, &euros, "GBP", date(2013, 1, 31)).map(|a| format_quantity(a.quantity(), 4)), Some("1.6667".to_string()));
+        assert!(convert(&prices, &euros, "CHF", date(2013, 1, 31)).is_none());
+        assert!(convert(&prices, &euros, "GBP", date(2013, 1, 4)).is_none());
+    }
 }
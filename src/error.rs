@@ -1,3 +1,51 @@
-pub fn error(line: usize, message: &str) {
-    eprintln!("[line {}] Error: {}", line, message)
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+/// An error or warning found while reading a journal, with the file and line it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    level: Level,
+    file: Option<PathBuf>,
+    line: usize,
+    message: String,
+}
+
+impl Diagnostic {
+    pub fn new(file: Option<&Path>, line: usize, message: &str) -> Diagnostic {
+        Diagnostic { level: Level::Error, file: file.map(|f| f.to_path_buf()), line, message: message.to_string() }
+    }
+    pub fn warning(file: Option<&Path>, line: usize, message: &str) -> Diagnostic {
+        Diagnostic { level: Level::Warning, ..Diagnostic::new(file, line, message) }
+    }
+    pub fn level(&self) -> Level {
+        self.level
+    }
+    pub fn file(&self) -> Option<&PathBuf> {
+        self.file.as_ref()
+    }
+    pub fn line(&self) -> usize {
+        self.line
+    }
+    pub fn message(&self) -> &String {
+        &self.message
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = match self.level {
+            Level::Error => "Error",
+            Level::Warning => "Warning",
+        };
+        match self.file {
+            Some(ref file) => write!(f, "[{} line {}] {}: {}", file.display(), self.line, level, self.message),
+            None => write!(f, "[line {}] {}: {}", self.line, level, self.message),
+        }
+    }
 }
@@ -0,0 +1,314 @@
+use accounting::{Amount, CommodityId, CommoditySymbol};
+use lexer::{Token, TokenType};
+use std::collections::BTreeMap;
+
+// Amount expressions, as written in parentheses in place of a posting amount:
+//
+//     expression → term
+//     term       → factor ( ( "+" | "-" ) factor )*
+//     factor     → unary ( ( "*" | "/" ) unary )*
+//     unary      → "-" unary | primary
+//     primary    → amount | variable | "(" expression ")"
+//     amount     → commodity? "-"? number commodity?
+//
+// A variable is a name given a value by `define`, such as `tax_rate`.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Binary { left: Box<Expr>, operator: Token, right: Box<Expr> },
+    Grouping(Box<Expr>),
+    Literal(Amount),
+    Unary { operator: Token, right: Box<Expr> },
+    Variable(String),
+}
+
+impl Expr {
+    pub fn evaluate(&self, scope: &Scope) -> Result<Amount, String> {
+        match *self {
+            Expr::Literal(ref amount) => Ok(amount.clone()),
+            Expr::Variable(ref name) => scope.get(name).cloned().ok_or_else(|| format!("Undefined variable '{}'", name)),
+            Expr::Grouping(ref expr) => expr.evaluate(scope),
+            Expr::Unary { ref right, .. } => Ok(-right.evaluate(scope)?),
+            Expr::Binary { ref left, ref operator, ref right } => {
+                let left = left.evaluate(scope)?;
+                let right = right.evaluate(scope)?;
+                match *operator.token_type() {
+                    TokenType::Plus => left.checked_add(right),
+                    TokenType::Hyphen => left.checked_sub(right),
+                    TokenType::Star => multiply(left, right),
+                    TokenType::Slash => left.checked_div(right),
+                    _ => Err(format!("Unknown operator '{}'", operator.lexeme())),
+                }
+            }
+        }
+    }
+}
+
+fn multiply(left: Amount, right: Amount) -> Result<Amount, String> {
+    if left.commodity() != CommodityId::NONE && right.commodity() != CommodityId::NONE {
+        return Err(format!("Cannot multiply {} by {}", left.symbol(), right.symbol()));
+    }
+    left.checked_mul(right)
+}
+
+/// The variables defined with `define`. Each `apply` block opens a scope of its own, whose
+/// definitions end with it.
+#[derive(Debug, Clone)]
+pub struct Scope {
+    frames: Vec<BTreeMap<String, Amount>>,
+}
+
+impl Default for Scope {
+    fn default() -> Scope {
+        Scope { frames: vec![BTreeMap::new()] }
+    }
+}
+
+impl Scope {
+    pub fn new() -> Scope {
+        Scope::default()
+    }
+    pub fn push(&mut self) {
+        self.frames.push(BTreeMap::new());
+    }
+    /// Ends the innermost scope. The outermost one is never ended.
+    pub fn pop(&mut self) {
+        if self.frames.len() > 1 {
+            self.frames.pop();
+        }
+    }
+    /// Defines a variable in the innermost scope, where it may shadow one of an outer scope. An
+    /// error if the innermost scope already defines it, in which case the new value replaces it.
+    pub fn define(&mut self, name: &str, value: Amount) -> Result<(), String> {
+        let frame = self.frames.last_mut().unwrap();
+        match frame.insert(name.to_string(), value) {
+            Some(_) => Err(format!("Variable '{}' is already defined", name)),
+            None => Ok(()),
+        }
+    }
+    pub fn get(&self, name: &str) -> Option<&Amount> {
+        self.frames.iter().rev().filter_map(|frame| frame.get(name)).next()
+    }
+}
+
+/// Parses a parenthesised amount expression such as `($120 / 3)`.
+pub fn parse(tokens: &[Token]) -> Result<Expr, String> {
+    let mut parser = ExpressionParser::new(tokens);
+    let expr = parser.primary()?;
+    parser.finish()?;
+    Ok(expr)
+}
+
+/// Parses a single amount such as `-$1,000.00` or `10 EUR`.
+pub fn parse_amount(tokens: &[Token]) -> Result<Amount, String> {
+    let mut parser = ExpressionParser::new(tokens);
+    let negative = parser.match_token(&TokenType::Hyphen);
+    let amount = parser.amount()?;
+    parser.finish()?;
+    Ok(if negative { -amount } else { amount })
+}
+
+/// Parses what may be written as an amount without parentheses: a single amount or a variable.
+pub fn parse_operand(tokens: &[Token]) -> Result<Expr, String> {
+    let mut parser = ExpressionParser::new(tokens);
+    if let Some(name) = parser.variable() {
+        parser.finish()?;
+        return Ok(Expr::Variable(name));
+    }
+    Ok(Expr::Literal(parse_amount(tokens)?))
+}
+
+struct ExpressionParser<'a> {
+    tokens: Vec<&'a Token>,
+    current: usize,
+}
+
+impl<'a> ExpressionParser<'a> {
+    fn new(tokens: &'a [Token]) -> ExpressionParser<'a> {
+        ExpressionParser {
+            tokens: tokens.iter().filter(|t| !t.is_whitespace() && *t.token_type() != TokenType::EOF).collect(),
+            current: 0,
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, String> {
+        self.term()
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut expr = self.factor()?;
+        while self.check(&TokenType::Plus) || self.check(&TokenType::Hyphen) {
+            let operator = self.advance().clone();
+            let right = self.factor()?;
+            expr = Expr::Binary { left: Box::new(expr), operator, right: Box::new(right) };
+        }
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.check(&TokenType::Star) || self.check(&TokenType::Slash) {
+            let operator = self.advance().clone();
+            let right = self.unary()?;
+            expr = Expr::Binary { left: Box::new(expr), operator, right: Box::new(right) };
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.check(&TokenType::Hyphen) {
+            let operator = self.advance().clone();
+            let right = self.unary()?;
+            return Ok(Expr::Unary { operator, right: Box::new(right) });
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        if self.match_token(&TokenType::LeftParen) {
+            let expr = self.expression()?;
+            if !self.match_token(&TokenType::RightParen) {
+                return Err("Expected ')' after expression".to_string());
+            }
+            return Ok(Expr::Grouping(Box::new(expr)));
+        }
+        if let Some(name) = self.variable() {
+            return Ok(Expr::Variable(name));
+        }
+        Ok(Expr::Literal(self.amount()?))
+    }
+
+    // A name that is not the commodity of an amount after it.
+    fn variable(&mut self) -> Option<String> {
+        let next = self.tokens.get(self.current + 1).map(|t| t.token_type());
+        if !self.check(&TokenType::String) || next == Some(&TokenType::Number) || next == Some(&TokenType::Hyphen) {
+            return None;
+        }
+        let name = self.peek()?.lexeme();
+        if !name.chars().all(|c| c.is_alphanumeric() || c == '_') || !name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            return None;
+        }
+        self.advance();
+        Some(name.clone())
+    }
+
+    fn amount(&mut self) -> Result<Amount, String> {
+        let mut commodity = self.commodity();
+        let negative = self.match_token(&TokenType::Hyphen);
+        if !self.check(&TokenType::Number) {
+            return Err(match self.peek() {
+                Some(token) => format!("Expected a number, found '{}'", token.lexeme()),
+                None => "Expected a number".to_string(),
+            });
+        }
+        let number = self.advance().lexeme().clone();
+        if commodity.is_empty() {
+            commodity = self.commodity();
+        }
+        let amount = Amount::parse(CommodityId::intern(&commodity), &number)?;
+        Ok(if negative { -amount } else { amount })
+    }
+
+    fn commodity(&mut self) -> CommoditySymbol {
+        if self.check(&TokenType::String) {
+            self.advance().lexeme().clone()
+        }
+        else {
+            "".to_string()
+        }
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        match self.peek() {
+            Some(token) => Err(format!("Unexpected '{}' in amount", token.lexeme())),
+            None => Ok(()),
+        }
+    }
+
+    fn match_token(&mut self, token_type: &TokenType) -> bool {
+        if self.check(token_type) {
+            self.advance();
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    fn check(&self, token_type: &TokenType) -> bool {
+        match self.peek() {
+            Some(token) => token.token_type() == token_type,
+            None => false,
+        }
+    }
+
+    fn advance(&mut self) -> &'a Token {
+        let token = self.tokens[self.current];
+        self.current += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.current).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::lex;
+
+    fn evaluate(s: &str) -> Result<Amount, String> {
+        parse(&lex(s))?.evaluate(&Scope::new())
+    }
+
+    fn amount(number: &str, commodity: &str) -> Amount {
+        Amount::parse(CommodityId::intern(commodity), number).unwrap()
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        let parsed = parse_amount(&lex("-$1,000.50")).unwrap();
+        assert_eq!(parsed, amount("-1000.5", "$"));
+        assert_eq!(parsed.precision(), 2);
+
+        let parsed = parse_amount(&lex("10 EUR")).unwrap();
+        assert_eq!(parsed, amount("10", "EUR"));
+        assert_eq!(parsed.precision(), 0);
+
+        assert!(parse_amount(&lex("$10 + $5")).is_err());
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(evaluate("($120 / 3 + 2 * $5)").unwrap(), amount("50", "$"));
+        assert_eq!(evaluate("(-(1 - 3) * 2.5 EUR)").unwrap(), amount("5", "EUR"));
+        assert_eq!(evaluate("($0.10 + $0.20 - $0.30)").unwrap(), amount("0", "$"));
+        assert_eq!(evaluate("($10 / 3 * 3)").unwrap(), amount("10", "$"));
+        assert_eq!(evaluate("($120 / $3)").unwrap(), amount("40", ""));
+    }
+
+    #[test]
+    fn test_mixed_commodities() {
+        assert!(evaluate("($10 + 5 EUR)").is_err());
+        assert!(evaluate("($10 * $2)").is_err());
+        assert!(evaluate("($10 / 0)").is_err());
+        assert!(evaluate("($10 + 5").is_err());
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut scope = Scope::new();
+        scope.define("tax_rate", amount("0.5", "")).unwrap();
+        assert_eq!(parse(&lex("($100 * tax_rate)")).unwrap().evaluate(&scope).unwrap(), amount("50", "$"));
+        assert!(parse(&lex("($100 * rate)")).unwrap().evaluate(&scope).is_err());
+
+        scope.push();
+        scope.define("tax_rate", amount("0.25", "")).unwrap();
+        assert_eq!(parse_operand(&lex("tax_rate")).unwrap().evaluate(&scope).unwrap(), amount("0.25", ""));
+        assert!(scope.define("tax_rate", amount("0.3", "")).is_err());
+        assert_eq!(scope.get("tax_rate"), Some(&amount("0.3", "")));
+        scope.pop();
+        assert_eq!(scope.get("tax_rate"), Some(&amount("0.5", "")));
+        assert_eq!(parse_operand(&lex("-$5")).unwrap().evaluate(&scope).unwrap(), amount("-5", "$"));
+    }
+}
@@ -0,0 +1,109 @@
+use regex::{escape, Regex};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Expands a path pattern relative to `base`. Within a single path component `*` matches any run
+/// of characters, `?` matches one character and `[...]` matches a character class. A pattern
+/// without any of these is returned as is, whether or not it exists, so that the caller can
+/// report a missing file. Only the pattern itself is expanded; `base` is taken literally.
+pub fn glob(base: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    if !is_pattern(pattern) {
+        return Ok(vec![base.join(pattern)]);
+    }
+
+    let pattern = Path::new(pattern);
+    let mut candidates = vec![if pattern.is_absolute() { PathBuf::new() } else { base.to_path_buf() }];
+    for component in pattern.components() {
+        let component = match component {
+            Component::Normal(name) => name.to_string_lossy().into_owned(),
+            other => {
+                candidates = candidates.iter().map(|c| c.join(other.as_os_str())).collect();
+                continue;
+            }
+        };
+        if !is_pattern(&component) {
+            candidates = candidates.iter().map(|c| c.join(&component)).collect();
+            continue;
+        }
+        let regex = to_regex(&component)?;
+        let mut matched = Vec::new();
+        for candidate in &candidates {
+            let dir = if candidate.as_os_str().is_empty() { Path::new(".") } else { candidate.as_path() };
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(dir).map_err(|err| err.to_string())? {
+                let name = entry.map_err(|err| err.to_string())?.file_name().to_string_lossy().into_owned();
+                if !name.starts_with('.') && regex.is_match(&name) {
+                    matched.push(candidate.join(name));
+                }
+            }
+        }
+        candidates = matched;
+    }
+
+    let mut paths: Vec<PathBuf> = candidates.into_iter().filter(|path| path.is_file()).collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn is_pattern(s: &str) -> bool {
+    s.contains('*') || s.contains('?') || s.contains('[')
+}
+
+fn to_regex(component: &str) -> Result<Regex, String> {
+    let mut regex = "^".to_string();
+    let mut in_class = false;
+    for c in component.chars() {
+        match c {
+            '*' if !in_class => regex.push_str(".*"),
+            '?' if !in_class => regex.push('.'),
+            '[' if !in_class => {
+                in_class = true;
+                regex.push('[');
+            }
+            ']' if in_class => {
+                in_class = false;
+                regex.push(']');
+            }
+            '!' if in_class && regex.ends_with('[') => regex.push('^'),
+            '\\' | '^' if in_class => regex.push_str(&escape(&c.to_string())),
+            _ if in_class => regex.push(c),
+            _ => regex.push_str(&escape(&c.to_string())),
+        }
+    }
+    if in_class {
+        regex = format!("^{}", escape(component));
+    }
+    regex.push('$');
+    Regex::new(&regex).map_err(|_| format!("Invalid pattern {}", component))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_glob() {
+        let dir = env::temp_dir().join(format!("ledger-glob-{}", ::std::process::id()));
+        fs::create_dir_all(dir.join("prices")).unwrap();
+        for name in &["prices/2013.db", "prices/2012.db", "prices/notes.txt", "2013.journal"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        assert_eq!(glob(&dir, "prices/*.db").unwrap(), vec![dir.join("prices/2012.db"), dir.join("prices/2013.db")]);
+        assert_eq!(glob(&dir, "p*/201[!2].db").unwrap(), vec![dir.join("prices/2013.db")]);
+        assert_eq!(glob(&dir, "*.journal").unwrap(), vec![dir.join("2013.journal")]);
+        assert_eq!(glob(&dir, "missing.journal").unwrap(), vec![dir.join("missing.journal")]);
+        assert!(glob(&dir, "missing/*.journal").unwrap().is_empty());
+        assert!(glob(&dir, "201[z-a].journal").is_err());
+
+        let bracketed = dir.join("[2013]");
+        fs::create_dir_all(&bracketed).unwrap();
+        fs::write(bracketed.join("a.journal"), "").unwrap();
+        assert_eq!(glob(&bracketed, "*.journal").unwrap(), vec![bracketed.join("a.journal")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,10 @@
 extern crate unicode_segmentation;
 
-// use regex::Regex;
 use unicode_segmentation::UnicodeSegmentation;
 use std::fs::File;
 use std::io::{BufReader, Error, Read};
+use std::path::Path;
 use std::result::Result;
-use error::error;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum TokenType {
@@ -15,28 +14,35 @@ pub enum TokenType {
     Space,
     Newline,
     Hyphen,
+    Plus,
+    Equals,
+    At,
+    LeftParen,
+    RightParen,
     Indentation,
     Modulo,
     Colon,
     Semicolon,
     Hash,
     Pipe,
+    Tilde,
     Number,
     String,
     EOF,
 }
 
-// We don't need a literal because we don't need to parse 
+// Every token keeps the text it was scanned from, so that free text such as descriptions and
+// account names can be put back together by the parser.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Token {
     token_type: TokenType,
-    lexeme: String, // Can be None
+    lexeme: String,
     line: usize,
 }
 
 impl Token {
     fn new(token_type: TokenType, lexeme: String, line: usize) -> Token {
-        Token { token_type: token_type, lexeme: lexeme, line: line }
+        Token { token_type, lexeme, line }
     }
     pub fn token_type(&self) -> &TokenType {
         &self.token_type
@@ -47,20 +53,19 @@ impl Token {
     pub fn lexeme(&self) -> &String {
         &self.lexeme
     }
+    pub fn is_whitespace(&self) -> bool {
+        self.token_type == TokenType::Space || self.token_type == TokenType::Indentation
+    }
 }
 
 trait AddToken {
-    fn add_token<'a>(&'a mut self, token_type: TokenType, grapheme: &str, line: usize);
-    fn add_token_type<'a>(&'a mut self, token_type: TokenType, line: usize);
+    fn add_token(&mut self, token_type: TokenType, grapheme: &str, line: usize);
 }
 
 impl AddToken for Vec<Token> {
-    fn add_token<'a>(&'a mut self, token_type: TokenType, grapheme: &str, line: usize) {
+    fn add_token(&mut self, token_type: TokenType, grapheme: &str, line: usize) {
         self.push(Token::new(token_type, grapheme.to_string(), line));
     }
-    fn add_token_type<'a>(&'a mut self, token_type: TokenType, line: usize) {
-        self.add_token(token_type, &"".to_string(), line);
-    }
 }
 
 struct Scanner {
@@ -71,20 +76,20 @@ struct Scanner {
     line: usize,
 }
 
-impl<'a> Scanner {
+impl Scanner {
     fn new(source: Vec<String>) -> Scanner {
-        Scanner { source: source, tokens: Vec::new(), start: 0, current: 0, line: 1 }
+        Scanner { source, tokens: Vec::new(), start: 0, current: 0, line: 1 }
     }
 
-    fn lex(&mut self) -> Result<Vec<Token>, ()> {
+    fn lex(&mut self) -> Vec<Token> {
         while !self.is_at_end() {
             self.start = self.current;
             self.scan_token();
         }
 
-        self.tokens.add_token(TokenType::EOF, &"".to_string(), self.line);
+        self.tokens.add_token(TokenType::EOF, "", self.line);
 
-        Ok(self.tokens.clone())
+        self.tokens.clone()
     }
 
     fn is_at_end(&self) -> bool {
@@ -93,51 +98,91 @@ impl<'a> Scanner {
 
     fn scan_token(&mut self) {
         self.current += 1;
-        let c = &self.source[self.current - 1];
+        let c = self.source[self.current - 1].clone();
         match c.as_str() {
-            "*" => {
-                self.tokens.add_token_type(TokenType::Star, self.line)
-            }
-            "/" => {
-                self.tokens.add_token_type(TokenType::Slash, self.line)
-            }
-            ";" => {
-                self.tokens.add_token_type(TokenType::Semicolon, self.line)
-            }
-            "%" => {
-                self.tokens.add_token_type(TokenType::Modulo, self.line)
-            }
-            "|" => {
-                self.tokens.add_token_type(TokenType::Pipe, self.line)
-            }
-            "-" => {
-                self.tokens.add_token_type(TokenType::Hyphen, self.line)
-            }
+            "*" => self.add_token_type(TokenType::Star),
+            "!" => self.add_token_type(TokenType::Bang),
+            "/" => self.add_token_type(TokenType::Slash),
+            ";" => self.add_token_type(TokenType::Semicolon),
+            "%" => self.add_token_type(TokenType::Modulo),
+            "|" => self.add_token_type(TokenType::Pipe),
+            "~" => self.add_token_type(TokenType::Tilde),
+            "#" => self.add_token_type(TokenType::Hash),
+            ":" => self.add_token_type(TokenType::Colon),
+            "-" => self.add_token_type(TokenType::Hyphen),
+            "+" => self.add_token_type(TokenType::Plus),
+            "=" => self.add_token_type(TokenType::Equals),
+            "@" => self.add_token_type(TokenType::At),
+            "(" => self.add_token_type(TokenType::LeftParen),
+            ")" => self.add_token_type(TokenType::RightParen),
             " " => {
-                if self.is_match(&" ".to_string()) {
-                    while self.peek() == " ".to_string() && !self.is_at_end() {
+                if self.is_match(" ") {
+                    while !self.is_at_end() && (self.peek() == " " || self.peek() == "\t") {
                         self.current += 1;
                     }
-                    self.tokens.add_token_type(TokenType::Indentation, self.line);
+                    self.add_token_type(TokenType::Indentation);
                 }
                 else {
-                    self.tokens.add_token_type(TokenType::Space, self.line);
+                    self.add_token_type(TokenType::Space);
+                }
+            }
+            "\t" => {
+                while !self.is_at_end() && (self.peek() == " " || self.peek() == "\t") {
+                    self.current += 1;
                 }
+                self.add_token_type(TokenType::Indentation);
             }
-            "\n" => {
-                self.tokens.add_token_type(TokenType::Newline, self.line);
+            "\n" | "\r\n" => {
+                self.add_token_type(TokenType::Newline);
+                self.line += 1;
             }
             _ => {
-                error(self.line, "Unexpected character.");
+                if is_digit(&c) || (c == "." && is_digit(&self.peek())) {
+                    self.number();
+                }
+                else {
+                    self.string();
+                }
+            }
+        }
+    }
+
+    // Digits, with any `.` or `,` that sits between two digits, as in `1,000.00`.
+    fn number(&mut self) {
+        while !self.is_at_end() {
+            let c = self.peek();
+            if is_digit(&c) || ((c == "." || c == ",") && is_digit(&self.peek_next())) {
+                self.current += 1;
+            }
+            else {
+                break;
+            }
+        }
+        self.add_token_type(TokenType::Number);
+    }
+
+    // Anything that is neither punctuation, whitespace nor the start of a number.
+    fn string(&mut self) {
+        while !self.is_at_end() {
+            let c = self.peek();
+            if is_delimiter(&c) || is_digit(&c) || (c == "." && is_digit(&self.peek_next())) {
+                break;
             }
+            self.current += 1;
         }
+        self.add_token_type(TokenType::String);
     }
 
-    fn is_match(&self, expected: &String) -> bool {
+    fn add_token_type(&mut self, token_type: TokenType) {
+        let lexeme = self.source[self.start..self.current].concat();
+        self.tokens.add_token(token_type, &lexeme, self.line);
+    }
+
+    fn is_match(&self, expected: &str) -> bool {
         if self.is_at_end() {
             return false
         }
-        if &self.source[self.current] != expected {
+        if self.source[self.current] != expected {
             return false
         }
         true
@@ -151,98 +196,79 @@ impl<'a> Scanner {
             self.source[self.current].clone()
         }
     }
+
+    fn peek_next(&self) -> String {
+        if self.current + 1 >= self.source.len() {
+            "\0".to_string()
+        }
+        else {
+            self.source[self.current + 1].clone()
+        }
+    }
+}
+
+fn is_digit(grapheme: &str) -> bool {
+    grapheme.len() == 1 && grapheme.as_bytes()[0].is_ascii_digit()
+}
+
+fn is_delimiter(grapheme: &str) -> bool {
+    matches!(grapheme, "*" | "!" | "/" | ";" | "%" | "|" | "~" | "#" | ":" | "-" | "+" | "=" | "@" | "(" | ")" |
+             " " | "\t" | "\n" | "\r\n")
 }
 
-#[allow(unused_must_use)]
-pub fn lex_file(s: &str) -> Result<Vec<Token>, Error> {
+pub fn lex_file<P: AsRef<Path>>(s: P) -> Result<Vec<Token>, Error> {
     let f = File::open(s)?;
     let mut file = BufReader::new(&f);
     let mut string: String = "".to_string();
     file.read_to_string(&mut string)?;
-    let mut results = Scanner::new(UnicodeSegmentation::graphemes(&string[..], true).map(|x| x.to_string()).collect::<Vec<String>>());
-    results.lex();
-    Ok(results.tokens)
+    Ok(lex(&string))
 }
 
-pub fn lex(string: &String) -> Vec<Token> {
-    let tokens: Vec<Token> = Vec::new();
-    let _graphemes = UnicodeSegmentation::graphemes(&string[..], true).collect::<Vec<&str>>();
+pub fn lex(string: &str) -> Vec<Token> {
+    let graphemes = UnicodeSegmentation::graphemes(string, true).map(|x| x.to_string()).collect::<Vec<String>>();
+    Scanner::new(graphemes).lex()
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::TokenType::*;
 
-    tokens
-}
+    fn types(s: &str) -> Vec<TokenType> {
+        lex(s).iter().map(|t| t.token_type().clone()).collect()
+    }
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     #[test]
-//     fn test_unicode_graphemes() {
-//         let s = "  Assets:Cash  $100.25\n";
-//         let w = UnicodeSegmentation::graphemes(s, true).collect::<Vec<&str>>();
-//         assert_eq!(w, &[" ", " ", "A", "s", "s", "e", "t", "s", ":", "C", "a", "s", "h", " ", " ", "$", "1", "0", "0", ".", "2", "5", "\n"])
-//     }
-//
-//     #[test]
-//     fn test_unicode_currency() {
-//         let s = "$1234.23";
-//         let w = s.split_word_bounds().collect::<Vec<&str>>();
-//         assert_eq!(w, &["$", "1234.23"]);
-//
-//         let s = "-$1234.23";
-//         let w = s.split_word_bounds().collect::<Vec<&str>>();
-//         assert_eq!(w, &["-", "$", "1234.23"]);
-//
-//         let s = "$-1234.23";
-//         let w = s.split_word_bounds().collect::<Vec<&str>>();
-//         assert_eq!(w, &["$", "-", "1234.23"]);
-//
-//         let s = "$.04";
-//         let w = s.split_word_bounds().collect::<Vec<&str>>();
-//         assert_eq!(w, &["$", ".", "04"]);
-//     }
-//
-//     #[test]
-//     fn test_lex_account() {
-//         let s = "  Assets:Cash  -$100.25\n".to_string();
-//         let lexed_line = lex(1, &s);
-//         assert_eq!(
-//             lexed_line,
-//             &[
-//                 Token::new( TokenType::Indentation, None, &"  ", 1 ),
-//                 Token::new( TokenType::AccountName, None, &"Assets:Cash", 1 ),
-//                 Token::new( TokenType::Indentation, None, &"  ", 1 ),
-//                 Token::new( TokenType::Currency, None, &"-$100.25", 1 ),
-//             ]
-//         );
-//
-//         let s = "  Assets:Cash\n".to_string();
-//         let lexed_line = lex(2, &s);
-//         assert_eq!(
-//             lexed_line,
-//             &[
-//                 Token::new( TokenType::Indentation, None, &"  ", 2 ),
-//                 Token::new( TokenType::AccountName, None, &"Assets:Cash", 2 ),
-//             ]
-//         );
-//     }
-//
-//     #[test]
-//     fn test_lex_date_description() {
-//         let s = "2014-01-01 * A Description\n".to_string();
-//         let lexed_line = lex(1, &s);
-//         assert_eq!(
-//             lexed_line,
-//             &[
-//                 Token::new( TokenType::Date, None, &"2014-01-01".to_string(), 1 ),
-//                 Token::new( TokenType::Status, None, &"*", 1 ),
-//                 Token::new( TokenType::Description, None, &"A Description", 1 ),
-//             ]
-//         );
-//     }
-//
-//     #[test]
-//     fn test_lex_file() {
-//         // TODO: Learn how one would test the ability to lex multiple lines. Probably not necessary
-//         // to do because we can trust BufRead to behave correctly.
-//     }
-// }
+    #[test]
+    fn test_lex_account() {
+        let tokens = lex("  Assets:Cash  -$100.25\n");
+        assert_eq!(
+            tokens.iter().map(|t| t.token_type().clone()).collect::<Vec<TokenType>>(),
+            vec![Indentation, String, Colon, String, Indentation, Hyphen, String, Number, Newline, EOF]
+        );
+        assert_eq!(tokens[6].lexeme(), "$");
+        assert_eq!(tokens[7].lexeme(), "100.25");
+    }
+
+    #[test]
+    fn test_lex_date_description() {
+        assert_eq!(
+            types("2014-01-01 * A Description\n"),
+            vec![Number, Hyphen, Number, Hyphen, Number, Space, Star, Space, String, Space, String, Newline, EOF]
+        );
+    }
+
+    #[test]
+    fn test_lex_numbers() {
+        let tokens = lex("$1,000.00 $.04 Inc.");
+        let lexemes = tokens.iter().map(|t| t.lexeme().as_str()).collect::<Vec<&str>>();
+        assert_eq!(lexemes, vec!["$", "1,000.00", " ", "$", ".04", " ", "Inc.", ""]);
+    }
+
+    #[test]
+    fn test_lex_is_lossless() {
+        let s = "2013-01-01 * Beginning Balance\n\tAssets:Cash  ($1 + 2)\r\n";
+        let joined = lex(s).iter().map(|t| t.lexeme().clone()).collect::<Vec<_>>().concat();
+        assert_eq!(joined, s);
+        assert_eq!(lex(s).last().unwrap().line(), 3);
+    }
+}
@@ -8,7 +8,16 @@ pub mod parser;
 pub mod lexer;
 pub mod status;
 pub mod error;
+// Kept as written; it predates the current lints.
+#[allow(dead_code, anonymous_parameters, bare_trait_objects, clippy::all)]
 pub mod parser_combinator;
+pub mod expression;
+pub mod query;
+pub mod glob;
+pub mod period;
+pub mod value;
+pub mod report;
+pub mod tree;
 
 /*
  * # TRANSACTIONS
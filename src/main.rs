@@ -1,9 +1,24 @@
+extern crate chrono;
 extern crate clap;
 extern crate ledger;
 
+use chrono::Local;
 use clap::{App, Arg, SubCommand};
+use ledger::parser::{parse_date, Options};
+use ledger::query::Query;
+use ledger::report;
+use ledger::report::Valuation;
+use std::io;
+use std::path::Path;
 // use ledger::accounting::{Transaction, Posting};
 
+fn query_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("query")
+        .value_name("QUERY")
+        .help("Only show postings matching the query, e.g. Expenses tag:work %Receipt")
+        .multiple(true)
+}
+
 fn main() {
     let matches = App::new("ledger-rs")
         .version("0.1")
@@ -14,9 +29,80 @@ fn main() {
                 .long("file")
                 .value_name("FILE")
                 .help("Set the file to use")
-                .takes_value(true),
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("alias")
+                .long("alias")
+                .value_name("OLD=NEW")
+                .help("Rename accounts as if by an alias directive")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("strict")
+                .long("strict")
+                .help("Warn about constructs that are probably mistakes, such as undeclared accounts")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("pedantic")
+                .long("pedantic")
+                .help("Like --strict, but fail instead of warning")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("cost-prices")
+                .long("cost-prices")
+                .help("Use the cost of each purchase as a price on its date")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("exchange")
+                .short("X")
+                .long("exchange")
+                .value_name("COMMODITY")
+                .help("Show amounts at their market value in COMMODITY")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("market")
+                .short("V")
+                .long("market")
+                .help("Show amounts at their market value in the commodity they are priced in")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("historical")
+                .short("H")
+                .long("historical")
+                .help("Value each posting at the prices on its own date")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("now")
+                .long("now")
+                .value_name("DATE")
+                .help("Value amounts at the prices on DATE rather than today")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(query_arg())
+        .subcommand(SubCommand::with_name("balance").arg(query_arg()))
+        .subcommand(SubCommand::with_name("register").arg(query_arg()))
+        .subcommand(
+            SubCommand::with_name("print")
+                .arg(query_arg())
+                .arg(
+                    Arg::with_name("generated")
+                        .long("generated")
+                        .help("Include postings added by automated transactions"),
+                ),
         )
-        .subcommand(SubCommand::with_name("balance"))
         .subcommand(SubCommand::with_name("budget"))
         .get_matches();
 
@@ -24,6 +110,19 @@ fn main() {
         .value_of("file")
         .unwrap_or("examples/example.journal");
 
+    let (command, command_matches) = match matches.subcommand() {
+        (name, Some(command_matches)) => (name, command_matches),
+        _ => ("", &matches),
+    };
+    let words: Vec<&str> = command_matches.values_of("query").map(|values| values.collect()).unwrap_or_default();
+    let query = match Query::parse(&words) {
+        Ok(query) => query,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+
     // let contents = ledger::read::read(file).unwrap();
     // let lines = contents.lines();
     // let mut ledger: Vec<Option<Transaction>> = Vec::new();
@@ -48,19 +147,62 @@ fn main() {
     // println!("{:?}", ledger);
     // println!("{:?}", postings);
 
-    let result = ledger::lexer::lex_file(file);
+    let mut options = Options::new();
+    options.set_strict(matches.is_present("strict"));
+    options.set_pedantic(matches.is_present("pedantic"));
+    options.set_cost_prices(matches.is_present("cost-prices"));
+    for alias in matches.values_of("alias").into_iter().flatten() {
+        if let Err(message) = options.add_alias(alias) {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    }
+
+    let valuation = if matches.is_present("exchange") || matches.is_present("market") {
+        let date = if matches.is_present("historical") {
+            None
+        }
+        else {
+            match matches.value_of("now").map(parse_date) {
+                Some(Ok(date)) => Some(date),
+                Some(Err(message)) => {
+                    eprintln!("{}", message);
+                    std::process::exit(1);
+                }
+                None => Some(Local::now().date_naive()),
+            }
+        };
+        Some(Valuation::new(matches.value_of("exchange").map(|c| c.to_string()), date))
+    }
+    else {
+        None
+    };
+
+    let result = ledger::parser::parse_file(Path::new(file), &options);
     match result {
-        Ok(res) => {
-            for token in &res {
-                println!("{:?}", token);
+        Ok(journal) => {
+            for diagnostic in journal.diagnostics() {
+                eprintln!("{}", diagnostic);
             }
-            let postings = ledger::parser::parse(res);
-            for posting in postings {
-                println!("{:?}", posting);
+            let stdout = io::stdout();
+            let result = match command {
+                "balance" => report::balance(&mut stdout.lock(), &journal, query.as_ref(), valuation.as_ref()),
+                "register" => report::register(&mut stdout.lock(), &journal, query.as_ref(), valuation.as_ref()),
+                "print" => report::print(&mut stdout.lock(), &journal, query.as_ref(), command_matches.is_present("generated")),
+                _ => {
+                    for posting in journal.postings().iter().filter(|p| query.as_ref().is_none_or(|q| q.matches(&journal, p))) {
+                        println!("{:?}", posting);
+                    }
+                    Ok(())
+                }
+            };
+            if let Err(err) = result {
+                eprintln!("{}", err);
             }
         }
         Err(res) => {
-            println!("Could not open and read file: {}", res);
+            eprintln!("Could not read {}: {}", file, res);
+            std::process::exit(1);
         }
     }
 }
@@ -1,147 +1,1943 @@
-#[allow(unused_imports)]
-use accounting::{Account, Transaction, Posting, Amount, CommoditySymbol};
-use lexer::{Token};
-// use lexer::TokenType::{Date, Indentation, Currency, CurrencyInferred, Description, AccountName, Status, EOF};
-// use error::error;
-// use chrono;
-// use chrono::Utc;
-// use status::Status as TxStatus;
-// use unicode_segmentation::UnicodeSegmentation;
+use accounting::{Account, AccountName, Amount, AutomatedAmount, AutomatedPosting, AutomatedTransaction, Balance, Commodity, CommodityId, CommodityStyle, FileId, Journal, Payee, PeriodicPosting, PeriodicTransaction, Posting, PostingKind, Price, Quantity, Tag, TagName, TagValue, Transaction, TransactionId};
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use error::{Diagnostic, Level};
+use expression;
+use expression::Scope;
+use glob::glob;
+use lexer;
+use lexer::{Token, TokenType};
+use num::{BigInt, Zero};
+use period::Period;
+use query::Query;
+use regex::{Regex, RegexBuilder};
+use status::Status;
+use value::{Value, ValueExpr};
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// Settings that apply to the whole of a journal, usually given on the command line.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    aliases: Vec<Alias>,
+    strict: bool,
+    pedantic: bool,
+    cost_prices: bool,
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options::default()
+    }
+    /// Warn about constructs that are accepted but probably mistaken.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+    /// Report what strict mode warns about as errors, and fail to read a journal with errors.
+    pub fn set_pedantic(&mut self, pedantic: bool) {
+        self.pedantic = pedantic;
+    }
+    /// Record the cost of each posting bought with `@` or `@@` as a price on the transaction's date.
+    pub fn set_cost_prices(&mut self, cost_prices: bool) {
+        self.cost_prices = cost_prices;
+    }
+    /// Adds an alias as if by an `alias` directive at the top of the journal.
+    pub fn add_alias(&mut self, definition: &str) -> Result<(), String> {
+        self.aliases.push(Alias::parse(definition)?);
+        Ok(())
+    }
+}
+
+// A block opened by `apply account` or `apply tag` and closed by the matching `end apply`.
+#[derive(Debug, Clone)]
+enum Apply {
+    Account(AccountName),
+    Tag(Vec<(TagName, Option<TagValue>)>),
+}
+
+/// A rewrite of account names, from `alias OLD=NEW` or `alias /REGEX/=REPLACEMENT`.
+#[derive(Debug, Clone)]
+pub enum Alias {
+    Account(AccountName, AccountName),
+    Pattern(Regex, String),
+}
+
+impl Alias {
+    pub fn parse(definition: &str) -> Result<Alias, String> {
+        let definition = definition.trim();
+        if definition.starts_with('/') {
+            // The pattern may itself contain `=`, so it ends at the first `/` followed by one.
+            let end = definition.match_indices('/')
+                .map(|(index, _)| index)
+                .find(|&index| index > 0 && definition[index + 1..].trim_start().starts_with('='))
+                .ok_or_else(|| format!("Expected /REGEX/=REPLACEMENT in alias {}", definition))?;
+            let pattern = &definition[1..end];
+            let replacement = definition[end + 1..].trim_start()[1..].trim();
+            let regex = RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map_err(|err| format!("Invalid alias pattern '{}': {}", pattern, err))?;
+            let backreference = Regex::new(r"\\(\d)").unwrap();
+            Ok(Alias::Pattern(regex, backreference.replace_all(replacement, "$${$1}").into_owned()))
+        }
+        else {
+            match definition.find('=') {
+                Some(index) if index > 0 && !definition[index + 1..].trim().is_empty() => {
+                    Ok(Alias::Account(definition[..index].trim().to_string(), definition[index + 1..].trim().to_string()))
+                }
+                _ => Err(format!("Expected OLD=NEW in alias {}", definition)),
+            }
+        }
+    }
+
+    // A plain alias matches the whole account name or its top-level segments, so that `alias
+    // Bank=Assets:Bank` also renames `Bank:Checking`.
+    fn apply(&self, name: &str) -> Option<AccountName> {
+        match *self {
+            Alias::Account(ref old, ref new) => {
+                if name == old {
+                    Some(new.clone())
+                }
+                else if name.starts_with(old.as_str()) && name[old.len()..].starts_with(':') {
+                    Some(format!("{}{}", new, &name[old.len()..]))
+                }
+                else {
+                    None
+                }
+            }
+            Alias::Pattern(ref regex, ref replacement) => {
+                if regex.is_match(name) {
+                    Some(regex.replace_all(name, replacement.as_str()).into_owned())
+                }
+                else {
+                    None
+                }
+            }
+        }
+    }
+}
 
 // Now to confabulate these disgraced and shattered things
-pub fn parse<'a>(_tokens: Vec<Token>) -> Vec<Posting> {    // let mut date: Option<chrono::Date<Utc>> = None;
-    // let mut status: TxStatus = TxStatus::Unmarked;
-    // let mut description = "".to_string();
-    // let mut transaction: Option<Transaction> = None;
-    // let mut current_account: Option<Account> = None;
-    //
-    let postings: Vec<Posting> = Vec::new();
-    //
-    // for token in tokens {
-    //     match token.token_type() {
-    //         &Date => {
-    //             // Reset
-    //             if date.is_some() {
-    //                 date = None;
-    //                 status = TxStatus::Unmarked;
-    //                 description.clear();
-    //                 transaction = None;
-    //                 current_account = None;
-    //             }
-    //
-    //             let date_string = token.literal();
-    //             let mut naive_date = chrono::NaiveDate::parse_from_str(date_string, "%Y-%m-%d");
-    //             if naive_date.is_ok() {
-    //                 date = Some(chrono::Date::from_utc(naive_date.unwrap(), chrono::Utc));
-    //             }
-    //             else {
-    //                 naive_date = chrono::NaiveDate::parse_from_str(date_string, "%Y-%m-%d");
-    //             }
-    //             if naive_date.is_ok() {
-    //                 date = Some(chrono::Date::from_utc(naive_date.unwrap(), chrono::Utc));
-    //             }
-    //             else {
-    //                 error(token.line(), "Date is not parseable");
-    //             }
-    //         }
-    //         &Status => {
-    //             if date.is_none() { error(token.line(), "No Date for Status"); }
-    //
-    //             // TODO: Would be nice to revise to not use the catch-all matching
-    //             let status_string = &token.literal();
-    //             status = match &status_string[..] {
-    //                 "!" => { TxStatus::Pending }
-    //                 "*" => { TxStatus::Cleared }
-    //                 _ => { TxStatus::Unmarked }
-    //             }
-    //         }
-    //         &Description => {
-    //             if date.is_none() { error(token.line(), "No Date for Description"); }
-    //
-    //             description.push_str(token.literal());
-    //
-    //             transaction = Some(Transaction::new(token.line(), date.unwrap(), status, description.clone()));
-    //         }
-    //         &Indentation => {
-    //             if date.is_none() { error(token.line(), "Stray indentation"); }
-    //         }
-    //         &AccountName => {
-    //             let literal = token.literal();
-    //             current_account = Some(Account::new(literal.clone()));
-    //         }
-    //         &Currency => {
-    //             let literal = token.literal().clone();
-    //             let split_words = literal.split_word_bounds().collect::<Vec<&str>>();
-    //             let iterable_words = split_words.iter();
-    //             let mut currency: Option<CommoditySymbol> = None;
-    //             let mut is_negative = false;
-    //             let mut starts_with_dot = false;
-    //             let mut amount: f64 = 0f64;
-    //
-    //             for word in iterable_words {
-    //                 match word {
-    //                     &"$" => {
-    //                         currency = Some(word.to_string())
-    //                     }
-    //                     &"-" => {
-    //                         is_negative = true;
-    //                     }
-    //                     &"." => {
-    //                         starts_with_dot = true;
-    //                     }
-    //                     &number => {
-    //                         let float_str: String =
-    //                             if starts_with_dot == true {
-    //                                 let mut s = ".".to_string();
-    //                                 s.push_str(number);
-    //                                 s
-    //                             }
-    //                             else {
-    //                                 number.to_string()
-    //                             };
-    //                         let parsed = float_str.parse();
-    //                         match parsed {
-    //                             Ok(parsed) => {
-    //                                 amount = parsed;
-    //                             }
-    //                             Err(err) => {
-    //                                 error(token.line(), &format!("{}: Could not parse {}", err, literal))
-    //                             }
-    //                         }
-    //                     }
-    //                 }
-    //             }
-    //             if is_negative == true {
-    //                 amount = -amount;
-    //             }
-    //
-    //             let amount = Amount::new(currency.unwrap(), amount);
-    //             postings.push(Posting::new(transaction.clone().unwrap(), current_account.clone().unwrap(), Some(amount)));
-    //         }
-    //         &CurrencyInferred => {
-    //             postings.push(Posting::new(transaction.clone().unwrap(), current_account.clone().unwrap(), None));
-    //         }
-    //         &EOF => {
-    //             // Done
-    //         }
-    //     }
-    // }
-
-    postings
+// Whatever is wrong with the journal is in its diagnostics.
+pub fn parse(tokens: Vec<Token>) -> Journal {
+    let mut parser = Parser::new(&Options::new());
+    parser.parse(tokens);
+    parser.check_assertions();
+    parser.finish()
+}
+
+/// Parses a journal file along with every file it includes. The errors and warnings found are in
+/// the journal's diagnostics, except that in pedantic mode any error fails the whole journal.
+pub fn parse_file(path: &Path, options: &Options) -> Result<Journal, Error> {
+    let mut parser = Parser::new(options);
+    parser.parse_file(path)?;
+    parser.check_assertions();
+    let errors = parser.diagnostics.iter().filter(|d| d.level() == Level::Error).count();
+    if options.pedantic && errors > 0 {
+        let diagnostics = parser.diagnostics.iter().map(|d| d.to_string()).collect::<Vec<String>>();
+        return Err(Error::new(ErrorKind::InvalidData, format!("{} has {} errors\n{}", path.display(), errors, diagnostics.join("\n"))));
+    }
+    Ok(parser.finish())
+}
+
+struct Parser {
+    options: Options,
+    tokens: Vec<Token>,
+    current: usize,
+    // The file being parsed, and the chain of files that included it.
+    file: Option<FileId>,
+    includes: Vec<PathBuf>,
+    transaction: Option<TransactionId>,
+    // The postings of the current transaction, which go into the journal once it ends.
+    postings: Vec<Posting>,
+    // Whether a line of the current transaction could not be read, so that its postings need not
+    // balance.
+    incomplete: bool,
+    // Aliases in force, from the command line and `alias` directives.
+    aliases: Vec<Alias>,
+    // Names that stand for declared accounts, and payee patterns that pick the account for a
+    // posting to an `Unknown` account.
+    account_aliases: HashMap<AccountName, AccountName>,
+    account_payees: Vec<(Regex, AccountName)>,
+    // Patterns of descriptions that stand for a declared payee.
+    payee_aliases: Vec<(Regex, String)>,
+    // Open `apply` blocks, innermost last.
+    applied: Vec<Apply>,
+    // The account that balances a transaction with a single posting.
+    bucket: Option<AccountName>,
+    // The year for dates written without one, from the last `year` directive.
+    year: Option<i32>,
+    // The open timeclock session, and when the last one ended.
+    clock_in: Option<ClockIn>,
+    clock_out: Option<NaiveDateTime>,
+    journal: Journal,
+    diagnostics: Vec<Diagnostic>,
+    // Undeclared accounts, commodities, payees and tags already reported, by kind and name.
+    undeclared: HashSet<(&'static str, String)>,
+    // `assert` and `check` directives, evaluated once the whole journal has been read.
+    assertions: Vec<Assertion>,
+    // Variables from `define`.
+    scope: Scope,
+}
+
+impl Parser {
+    fn new(options: &Options) -> Parser {
+        Parser {
+            options: options.clone(),
+            tokens: Vec::new(),
+            current: 0,
+            file: None,
+            includes: Vec::new(),
+            transaction: None,
+            postings: Vec::new(),
+            incomplete: false,
+            aliases: options.aliases.clone(),
+            account_aliases: HashMap::new(),
+            account_payees: Vec::new(),
+            payee_aliases: Vec::new(),
+            applied: Vec::new(),
+            bucket: None,
+            year: None,
+            clock_in: None,
+            clock_out: None,
+            journal: Journal::new(),
+            diagnostics: Vec::new(),
+            undeclared: HashSet::new(),
+            assertions: Vec::new(),
+            scope: Scope::new(),
+        }
+    }
+
+    fn parse(&mut self, tokens: Vec<Token>) {
+        let tokens = mem::replace(&mut self.tokens, tokens);
+        let current = mem::replace(&mut self.current, 0);
+        while !self.is_at_end() {
+            let start = self.current;
+            let line = self.peek().line();
+            if let Err(message) = self.line() {
+                self.error(line, &message);
+                self.incomplete = self.transaction.is_some();
+                self.synchronize(start);
+            }
+        }
+        self.end_transaction();
+        if self.clock_in.as_ref().is_some_and(|clock_in| clock_in.file == self.file) {
+            let clock_in = self.clock_in.take().unwrap();
+            self.error(clock_in.line, &format!("Check-in to {} is never checked out", clock_in.account));
+        }
+        self.tokens = tokens;
+        self.current = current;
+    }
+
+    fn parse_file(&mut self, path: &Path) -> Result<(), Error> {
+        let tokens = lexer::lex_file(path)?;
+        let canonical = path.canonicalize()?;
+        let file = self.file.replace(self.journal.add_file(path));
+        self.includes.push(canonical);
+        self.parse(tokens);
+        self.includes.pop();
+        self.file = file;
+        Ok(())
+    }
+
+    fn error(&mut self, line: usize, message: &str) {
+        let diagnostic = Diagnostic::new(self.file.map(|id| self.journal.file(id)), line, message);
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn warning(&mut self, line: usize, message: &str) {
+        let diagnostic = Diagnostic::warning(self.file.map(|id| self.journal.file(id)), line, message);
+        self.diagnostics.push(diagnostic);
+    }
+
+    // Something strict mode warns about, and pedantic mode treats as an error.
+    fn strict(&mut self, line: usize, message: &str) {
+        if self.options.pedantic {
+            self.error(line, message);
+        }
+        else if self.options.strict {
+            self.warning(line, message);
+        }
+    }
+
+    // In strict mode, the first use of each account, commodity, payee or tag that no directive
+    // declared before it.
+    fn check_declared(&mut self, line: usize, kind: &'static str, name: &str) {
+        if !self.options.strict && !self.options.pedantic {
+            return;
+        }
+        let declared = match kind {
+            "account" => self.journal.find_account(name).is_some_and(|id| self.journal.account(id).is_declared()),
+            "commodity" => self.journal.find_commodity(name).is_some(),
+            "payee" => self.journal.is_payee_declared(name),
+            _ => self.journal.is_tag_declared(name),
+        };
+        if !declared && self.undeclared.insert((kind, name.to_string())) {
+            self.strict(line, &format!("Unknown {} '{}'", kind, name));
+        }
+    }
+
+    // An `assert` or `check` at the top level, or within the block of `account` or `tag`.
+    fn assertion(&mut self, line: usize, level: Level, text: &str, target: Target) {
+        match ValueExpr::parse(text) {
+            Ok(expression) => self.assertions.push(Assertion {
+                file: self.file,
+                line,
+                level,
+                text: text.to_string(),
+                expression,
+                target,
+            }),
+            Err(message) => self.error(line, &message),
+        }
+    }
+
+    // A top level assertion is evaluated once, one in an account block for every posting to the
+    // account, and one in a tag block for every posting with the tag, stopping at the first that
+    // fails.
+    fn check_assertions(&mut self) {
+        for assertion in mem::take(&mut self.assertions) {
+            let what = if assertion.level == Level::Error { "Assertion" } else { "Check" };
+            let journal = &self.journal;
+            let failed = |posting: &Posting| {
+                let transaction = journal.transaction(posting.transaction());
+                format!("{} failed for {} on {}: {}", what, transaction.payee(), transaction.date().format("%Y/%m/%d"), assertion.text)
+            };
+            let failure = match assertion.target {
+                Target::Journal => match assertion.expression.evaluate(&self.journal, None) {
+                    Ok(value) if value.is_true() => None,
+                    Ok(_) => Some(format!("{} failed: {}", what, assertion.text)),
+                    Err(message) => Some(message),
+                },
+                Target::Account(ref account) => self.journal.postings().iter()
+                    .filter(|posting| self.journal.account(posting.account()).name() == account)
+                    .filter_map(|posting| match assertion.expression.evaluate(&self.journal, Some(posting)) {
+                        Ok(value) if value.is_true() => None,
+                        Ok(_) => Some(failed(posting)),
+                        Err(message) => Some(message),
+                    })
+                    .next(),
+                Target::Tag(ref tag) => self.journal.postings().iter()
+                    .filter_map(|posting| {
+                        let value = posting.tags().get(tag)?;
+                        match assertion.expression.evaluate_tag(&self.journal, posting, &tag_value(value.as_ref())) {
+                            Ok(value) if value.is_true() => None,
+                            Ok(_) => Some(failed(posting)),
+                            Err(message) => Some(message),
+                        }
+                    })
+                    .next(),
+            };
+            if let Some(message) = failure {
+                let diagnostic = match assertion.level {
+                    Level::Error => Diagnostic::new(assertion.file.map(|id| self.journal.file(id)), assertion.line, &message),
+                    Level::Warning => Diagnostic::warning(assertion.file.map(|id| self.journal.file(id)), assertion.line, &message),
+                };
+                self.diagnostics.push(diagnostic);
+            }
+        }
+    }
+
+    // The journal read, with what was found wrong with it.
+    fn finish(mut self) -> Journal {
+        for diagnostic in mem::take(&mut self.diagnostics) {
+            self.journal.add_diagnostic(diagnostic);
+        }
+        self.journal
+    }
+
+    // Like Python, a journal is made of lines, and indentation is what ties postings to the
+    // transaction above them. A blank line or anything at the start of a line closes the current
+    // transaction.
+    fn line(&mut self) -> Result<(), String> {
+        match *self.peek().token_type() {
+            TokenType::Newline => {
+                self.advance();
+                self.end_transaction();
+                Ok(())
+            }
+            TokenType::Indentation => {
+                self.advance();
+                if self.check(&TokenType::Newline) || self.is_at_end() {
+                    self.end_transaction();
+                    return Ok(());
+                }
+                if self.match_token(&TokenType::Semicolon) {
+                    let note = self.text_until(&[TokenType::Newline]);
+                    self.skip_line();
+                    return self.note(note.trim());
+                }
+                if self.transaction.is_none() {
+                    return Err("Stray indentation".to_string());
+                }
+                self.posting()
+            }
+            TokenType::Number => {
+                self.end_transaction();
+                let result = self.transaction();
+                if result.is_err() && self.transaction.is_none() {
+                    // The postings belong to the header in error, so they are skipped with it.
+                    if *self.previous().token_type() != TokenType::Newline {
+                        self.skip_line();
+                    }
+                    while self.indented_line() {
+                        self.skip_line();
+                    }
+                }
+                result
+            }
+            TokenType::Semicolon | TokenType::Hash | TokenType::Modulo | TokenType::Pipe | TokenType::Star => {
+                self.end_transaction();
+                self.skip_line();
+                Ok(())
+            }
+            TokenType::Equals => {
+                self.end_transaction();
+                self.automated_transaction()
+            }
+            TokenType::Tilde => {
+                self.end_transaction();
+                self.periodic_transaction()
+            }
+            TokenType::String => {
+                self.end_transaction();
+                self.directive()
+            }
+            _ => {
+                self.end_transaction();
+                Err(format!("Unexpected '{}' at start of line", self.peek().lexeme()))
+            }
+        }
+    }
+
+    fn directive(&mut self) -> Result<(), String> {
+        let line = self.peek().line();
+        let name = self.advance().lexeme().clone();
+        self.skip_whitespace();
+        let argument = self.text_until(&[TokenType::Newline]);
+        self.skip_line();
+        match name.as_str() {
+            "account" => self.account_directive(argument.trim()),
+            "alias" => {
+                self.aliases.push(Alias::parse(&argument)?);
+                Ok(())
+            }
+            "apply" => self.apply_directive(argument.trim()),
+            "commodity" => self.commodity_directive(argument.trim()),
+            "define" => self.define_directive(line, argument.trim()),
+            "C" => {
+                let (larger, smaller) = match argument.find('=') {
+                    Some(index) => (&argument[..index], &argument[index + 1..]),
+                    None => return Err("Expected a conversion such as 'C 1h = 60m'".to_string()),
+                };
+                let larger = expression::parse_amount(&lexer::lex(larger))?;
+                let smaller = expression::parse_amount(&lexer::lex(smaller))?;
+                self.journal.add_conversion(&larger, &smaller)
+            }
+            "D" => {
+                let (symbol, style) = CommodityStyle::parse(argument.trim())?;
+                let id = CommodityId::intern(&symbol);
+                let mut commodity = self.journal.commodity(id).cloned().unwrap_or_else(|| Commodity::new(id));
+                commodity.set_style(style);
+                commodity.set_default(true);
+                self.journal.declare_commodity(commodity);
+                Ok(())
+            }
+            "N" => {
+                let symbol = argument.trim();
+                if symbol.is_empty() {
+                    return Err("Expected commodity".to_string());
+                }
+                let id = CommodityId::intern(symbol);
+                let mut commodity = self.journal.commodity(id).cloned().unwrap_or_else(|| Commodity::new(id));
+                commodity.set_nomarket(true);
+                self.journal.declare_commodity(commodity);
+                Ok(())
+            }
+            "bucket" | "A" => {
+                if argument.trim().is_empty() {
+                    return Err("Expected account name".to_string());
+                }
+                self.bucket = Some(self.account_name(argument.trim()));
+                Ok(())
+            }
+            "end" => self.end_directive(argument.trim()),
+            "include" => self.include(argument.trim()),
+            "P" => self.price_directive(argument.trim()),
+            "assert" => {
+                self.assertion(line, Level::Error, argument.trim(), Target::Journal);
+                Ok(())
+            }
+            "check" => {
+                self.assertion(line, Level::Warning, argument.trim(), Target::Journal);
+                Ok(())
+            }
+            "payee" => self.payee_directive(argument.trim()),
+            "tag" => self.tag_directive(argument.trim()),
+            "i" | "I" => self.check_in(line, argument.trim()),
+            "o" | "O" => self.check_out(argument.trim()),
+            "year" | "Y" => {
+                let year = argument.trim().parse().map_err(|_| format!("Expected a year, found '{}'", argument.trim()))?;
+                self.year = Some(year);
+                Ok(())
+            }
+            _ => Err(format!("Unknown directive '{}'", name)),
+        }
+    }
+
+    // `define NAME=VALUE`, where the value is an amount or an expression in parentheses.
+    fn define_directive(&mut self, line: usize, argument: &str) -> Result<(), String> {
+        let (name, value) = match argument.find('=') {
+            Some(index) => (argument[..index].trim(), argument[index + 1..].trim()),
+            None => return Err("Expected 'define NAME=VALUE'".to_string()),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(format!("Invalid variable name '{}'", name));
+        }
+        let tokens = lexer::lex(value);
+        let expr = if value.starts_with('(') { expression::parse(&tokens)? } else { expression::parse_operand(&tokens)? };
+        let value = expr.evaluate(&self.scope)?;
+        if let Err(message) = self.scope.define(name, value) {
+            self.warning(line, &message);
+        }
+        Ok(())
+    }
+
+    fn apply_directive(&mut self, argument: &str) -> Result<(), String> {
+        let (kind, value) = match argument.find(char::is_whitespace) {
+            Some(index) => (&argument[..index], argument[index..].trim()),
+            None => (argument, ""),
+        };
+        if value.is_empty() {
+            return Err(format!("Expected a value after 'apply {}'", kind));
+        }
+        match kind {
+            "account" => self.applied.push(Apply::Account(value.to_string())),
+            "tag" => {
+                // Either `apply tag work` or a note such as `apply tag Client: Acme`.
+                let mut tags = parse_tags(value)?;
+                if tags.is_empty() {
+                    tags.push((value.to_string(), None));
+                }
+                self.applied.push(Apply::Tag(tags));
+            }
+            _ => return Err(format!("Unknown directive 'apply {}'", kind)),
+        }
+        self.scope.push();
+        Ok(())
+    }
+
+    fn end_directive(&mut self, argument: &str) -> Result<(), String> {
+        match argument {
+            "aliases" => {
+                self.aliases = self.options.aliases.clone();
+                Ok(())
+            }
+            "apply" | "apply account" | "apply tag" => {
+                let opened = match self.applied.last() {
+                    Some(&Apply::Account(_)) => "apply account",
+                    Some(&Apply::Tag(_)) => "apply tag",
+                    None => return Err(format!("'end {}' without a matching 'apply'", argument)),
+                };
+                if argument != "apply" && argument != opened {
+                    return Err(format!("'end {}' does not match '{}'", argument, opened));
+                }
+                self.applied.pop();
+                self.scope.pop();
+                Ok(())
+            }
+            _ => Err(format!("Unknown directive 'end {}'", argument)),
+        }
+    }
+
+    // The indented lines beneath a directive, as (line, name, argument). Comments among them are
+    // skipped.
+    fn subdirectives(&mut self) -> Vec<(usize, String, String)> {
+        let mut subdirectives = Vec::new();
+        while self.check(&TokenType::Indentation) {
+            match self.tokens.get(self.current + 1).map(|t| t.token_type()) {
+                Some(&TokenType::Newline) | Some(&TokenType::EOF) | None => break,
+                _ => {}
+            }
+            self.advance();
+            let line = self.peek().line();
+            if self.check(&TokenType::Semicolon) {
+                self.skip_line();
+                continue;
+            }
+            let name = self.advance().lexeme().clone();
+            self.skip_whitespace();
+            let argument = self.text_until(&[TokenType::Newline]);
+            self.skip_line();
+            subdirectives.push((line, name, argument.trim().to_string()));
+        }
+        subdirectives
+    }
+
+    // The argument is either the symbol or a sample amount that gives the format as well, as in
+    // `commodity $1,000.00`.
+    fn commodity_directive(&mut self, argument: &str) -> Result<(), String> {
+        if argument.is_empty() {
+            return Err("Expected commodity".to_string());
+        }
+        let mut commodity = if argument.contains(|c: char| c.is_ascii_digit()) {
+            let (symbol, style) = CommodityStyle::parse(argument)?;
+            let mut commodity = Commodity::new(CommodityId::intern(&symbol));
+            commodity.set_style(style);
+            commodity
+        }
+        else {
+            Commodity::new(CommodityId::intern(argument))
+        };
+        for (line, subdirective, argument) in self.subdirectives() {
+            match subdirective.as_str() {
+                "note" => commodity.add_note(&argument),
+                "format" => match CommodityStyle::parse(&argument) {
+                    Ok((ref symbol, ref style)) if symbol == commodity.symbol() => commodity.set_style(style.clone()),
+                    Ok(_) => self.error(line, &format!("Format {} is not for commodity {}", argument, commodity.symbol())),
+                    Err(message) => self.error(line, &message),
+                },
+                "nomarket" => commodity.set_nomarket(true),
+                "default" => commodity.set_default(true),
+                _ => self.error(line, &format!("Unknown commodity subdirective '{}'", subdirective)),
+            }
+        }
+        self.journal.declare_commodity(commodity);
+        Ok(())
+    }
+
+    // `P DATE [TIME] COMMODITY AMOUNT`: the price of one unit of the commodity on that date.
+    fn price_directive(&mut self, argument: &str) -> Result<(), String> {
+        let mut words = argument.split_whitespace();
+        let date = parse_date(words.next().ok_or("Expected a date")?)?;
+        let mut commodity = words.next().ok_or("Expected a commodity")?;
+        if commodity.contains(':') {
+            // Ledger allows a time of day, which does not matter for daily prices.
+            commodity = words.next().ok_or("Expected a commodity")?;
+        }
+        let price = words.collect::<Vec<&str>>().join(" ");
+        if price.is_empty() {
+            return Err(format!("Expected a price for {}", commodity));
+        }
+        let price = expression::parse_amount(&lexer::lex(&price))?;
+        self.journal.add_price(Price::new(date, CommodityId::intern(commodity.trim_matches('"')), price));
+        Ok(())
+    }
+
+    fn payee_directive(&mut self, name: &str) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("Expected payee".to_string());
+        }
+        let mut payee = Payee::new(name.to_string());
+        for (line, subdirective, argument) in self.subdirectives() {
+            match subdirective.as_str() {
+                "alias" => match Regex::new(&argument) {
+                    Ok(regex) => {
+                        self.payee_aliases.push((regex, name.to_string()));
+                        payee.add_alias(argument);
+                    }
+                    Err(err) => self.error(line, &format!("Invalid payee alias '{}': {}", argument, err)),
+                },
+                _ => self.error(line, &format!("Unknown payee subdirective '{}'", subdirective)),
+            }
+        }
+        self.journal.declare_payee(payee);
+        Ok(())
+    }
+
+    fn tag_directive(&mut self, name: &str) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("Expected tag".to_string());
+        }
+        let mut tag = Tag::new(name.to_string());
+        for (line, subdirective, argument) in self.subdirectives() {
+            match subdirective.as_str() {
+                "check" => {
+                    self.assertion(line, Level::Warning, &argument, Target::Tag(name.to_string()));
+                    tag.add_check(argument);
+                }
+                "assert" => {
+                    self.assertion(line, Level::Error, &argument, Target::Tag(name.to_string()));
+                    tag.add_assertion(argument);
+                }
+                _ => self.error(line, &format!("Unknown tag subdirective '{}'", subdirective)),
+            }
+        }
+        self.journal.declare_tag(tag);
+        Ok(())
+    }
+
+    // The payee a description stands for: the declared payee with the first alias that matches
+    // it, or else the description itself.
+    fn payee(&self, description: &str) -> String {
+        self.payee_aliases.iter()
+            .find(|payee_alias| payee_alias.0.is_match(description))
+            .map_or_else(|| description.to_string(), |payee_alias| payee_alias.1.clone())
+    }
+
+    // `i DATE TIME ACCOUNT  [PAYEE]` starts a timeclock session.
+    fn check_in(&mut self, line: usize, argument: &str) -> Result<(), String> {
+        let (time, rest) = timestamp(argument)?;
+        let (account, payee) = match rest.find("  ").or_else(|| rest.find('\t')) {
+            Some(index) => (rest[..index].trim(), rest[index..].trim()),
+            None => (rest, ""),
+        };
+        if account.is_empty() {
+            return Err("Expected account name".to_string());
+        }
+        if let Some(ref open) = self.clock_in {
+            return Err(format!("Checked in to {} while still checked in to {} since {}", account, open.account, open.time));
+        }
+        if let Some(last) = self.clock_out {
+            if time < last {
+                return Err(format!("Check-in at {} overlaps the session that ended at {}", time, last));
+            }
+        }
+        let account = self.applied_account_name(account);
+        let description = if payee.is_empty() { account.clone() } else { payee.to_string() };
+        let payee = self.payee(&description);
+        self.check_declared(line, "account", &account);
+        self.check_declared(line, "payee", &payee);
+        self.check_declared(line, "commodity", "h");
+        self.clock_in = Some(ClockIn { file: self.file, line, time, account, description, payee });
+        Ok(())
+    }
+
+    // `o DATE TIME` ends the session, which becomes a transaction on the day it started with a
+    // virtual posting of the hours worked.
+    fn check_out(&mut self, argument: &str) -> Result<(), String> {
+        let (time, _) = timestamp(argument)?;
+        let clock_in = self.clock_in.take().ok_or("Check-out without a check-in")?;
+        if time < clock_in.time {
+            let message = format!("Check-out at {} is before the check-in at {}", time, clock_in.time);
+            self.clock_in = Some(clock_in);
+            return Err(message);
+        }
+        self.clock_out = Some(time);
+        let hours = Quantity::new(BigInt::from((time - clock_in.time).num_seconds()), BigInt::from(3600));
+        let mut transaction = Transaction::new(clock_in.file, clock_in.line, clock_in.time.date(), None, Status::Unmarked, None, clock_in.description);
+        transaction.set_payee(clock_in.payee);
+        let transaction = self.journal.add_transaction(transaction);
+        let account = self.journal.add_account(&clock_in.account);
+        let mut posting = Posting::new(transaction, account, Some(Amount::new(CommodityId::intern("h"), hours)));
+        posting.set_kind(PostingKind::Virtual);
+        self.journal.add_posting(posting);
+        Ok(())
+    }
+
+    fn account_directive(&mut self, name: &str) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("Expected account name".to_string());
+        }
+        let mut account = Account::new(name.to_string());
+        for (line, subdirective, argument) in self.subdirectives() {
+            match subdirective.as_str() {
+                "note" => account.add_note(&argument),
+                "alias" => {
+                    self.account_aliases.insert(argument.clone(), name.to_string());
+                    account.add_alias(argument);
+                }
+                "payee" => match Regex::new(&argument) {
+                    Ok(regex) => {
+                        self.account_payees.push((regex, name.to_string()));
+                        account.add_payee(argument);
+                    }
+                    Err(err) => self.error(line, &format!("Invalid payee pattern '{}': {}", argument, err)),
+                },
+                "check" => {
+                    self.assertion(line, Level::Warning, &argument, Target::Account(name.to_string()));
+                    account.add_check(argument);
+                }
+                "assert" => {
+                    self.assertion(line, Level::Error, &argument, Target::Account(name.to_string()));
+                    account.add_assertion(argument);
+                }
+                "default" => {
+                    self.bucket = Some(name.to_string());
+                    account.set_default(true);
+                }
+                _ => self.error(line, &format!("Unknown account subdirective '{}'", subdirective)),
+            }
+        }
+        self.journal.declare_account(account);
+        Ok(())
+    }
+
+    // Included paths are relative to the including file, and may be glob patterns.
+    fn include(&mut self, pattern: &str) -> Result<(), String> {
+        let base = self.file.and_then(|id| self.journal.file(id).parent()).map(|p| p.to_path_buf()).unwrap_or_default();
+        let paths = glob(&base, pattern).map_err(|err| format!("Could not include {}: {}", pattern, err))?;
+        if paths.is_empty() {
+            return Err(format!("No files match {}", pattern));
+        }
+        for path in paths {
+            let canonical = path.canonicalize().map_err(|err| format!("Could not include {}: {}", path.display(), err))?;
+            if self.includes.contains(&canonical) {
+                let chain = self.includes.iter()
+                    .chain(Some(&canonical))
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<String>>();
+                return Err(format!("Include cycle: {}", chain.join(" -> ")));
+            }
+            self.parse_file(&path).map_err(|err| format!("Could not include {}: {}", path.display(), err))?;
+        }
+        Ok(())
+    }
+
+    // The postings of the transaction go into the journal once they balance, followed by those
+    // that automated transactions generate for them.
+    fn end_transaction(&mut self) {
+        let transaction = match self.transaction.take() {
+            Some(transaction) => transaction,
+            None => return,
+        };
+        if let Some(ref bucket) = self.bucket {
+            if self.postings.len() == 1 {
+                let account = self.journal.add_account(bucket);
+                self.postings.push(Posting::new(transaction, account, None));
+            }
+        }
+        let incomplete = mem::replace(&mut self.incomplete, false);
+        for kind in &[PostingKind::Real, PostingKind::BalancedVirtual] {
+            if let Err(message) = self.balance_transaction(*kind) {
+                if incomplete {
+                    continue;
+                }
+                let line = self.journal.transaction(transaction).line();
+                self.error(line, &message);
+            }
+        }
+        for posting in self.postings.drain(..) {
+            self.journal.add_posting(posting);
+        }
+        let journal = &self.journal;
+        let postings = journal.transaction(transaction).postings();
+        let generated = journal.automated_transactions().iter()
+            .flat_map(|automated| postings.iter().flat_map(move |&id| automated.generate(journal, journal.posting(id))))
+            .collect::<Vec<Posting>>();
+        for posting in generated {
+            self.journal.add_posting(posting);
+        }
+    }
+
+    // A posting without an amount takes whatever balances the others, one posting for each
+    // commodity. Otherwise the amounts must add up to exactly zero. Real postings and balanced
+    // virtual postings each balance on their own.
+    fn balance_transaction(&mut self, kind: PostingKind) -> Result<(), String> {
+        let mut sum = Balance::new();
+        for amount in self.postings.iter().filter(|p| p.kind() == kind).filter_map(|p| p.value()) {
+            sum += self.journal.reduce(&amount);
+        }
+        let elided = self.postings.iter().filter(|p| p.kind() == kind && p.amount().is_none()).count();
+        if elided > 1 {
+            // There is no telling which amount each would take, so none of them is kept.
+            self.postings.retain(|p| p.kind() != kind || p.amount().is_some());
+            return Err("Only one posting may omit its amount".to_string());
+        }
+        if elided == 1 {
+            let index = self.postings.iter().position(|p| p.kind() == kind && p.amount().is_none()).unwrap();
+            let posting = self.postings.remove(index);
+            let mut balancing = Vec::new();
+            for amount in (-sum).amounts() {
+                let mut posting = posting.clone();
+                posting.set_amount(Some(self.journal.unreduce(amount)));
+                balancing.push(posting);
+            }
+            if balancing.is_empty() {
+                let mut posting = posting;
+                posting.set_amount(Some(Amount::new(CommodityId::NONE, Quantity::zero())));
+                balancing.push(posting);
+            }
+            for (offset, posting) in balancing.into_iter().enumerate() {
+                self.postings.insert(index + offset, posting);
+            }
+            return Ok(());
+        }
+        let unbalanced = sum.amounts()
+            .map(|amount| self.journal.format_amount(&self.journal.unreduce(amount)))
+            .collect::<Vec<String>>();
+        if !unbalanced.is_empty() {
+            return Err(format!("Transaction does not balance: {} left over", unbalanced.join(", ")));
+        }
+        Ok(())
+    }
+
+    // `= PREDICATE` followed by postings. The predicate is a query, in which `/regex/` is the same
+    // as a plain account pattern.
+    fn automated_transaction(&mut self) -> Result<(), String> {
+        self.advance();
+        let predicate = self.text_until(&[TokenType::Newline]);
+        self.skip_line();
+        let words = predicate.split_whitespace()
+            .map(|word| if word.len() > 1 && word.starts_with('/') && word.ends_with('/') { &word[1..word.len() - 1] } else { word })
+            .collect::<Vec<&str>>();
+        let predicate = Query::parse(&words)?.ok_or("Expected a predicate after '='")?;
+        let mut automated = AutomatedTransaction::new(predicate);
+        while self.indented_line() {
+            let line = self.peek().line();
+            match self.automated_posting() {
+                Ok(posting) => automated.add_posting(posting),
+                Err(message) => self.error(line, &message),
+            }
+            self.skip_line();
+        }
+        self.journal.add_automated_transaction(automated);
+        Ok(())
+    }
+
+    // `~ PERIOD` followed by postings.
+    fn periodic_transaction(&mut self) -> Result<(), String> {
+        let line = self.advance().line();
+        let period = self.text_until_note();
+        self.skip_line();
+        let period = match Period::parse(&period) {
+            Ok(period) => period,
+            Err(message) => {
+                // The postings belong to the period in error, so they are skipped with it.
+                while self.indented_line() {
+                    self.skip_line();
+                }
+                return Err(message);
+            }
+        };
+        let mut periodic = PeriodicTransaction::new(self.file, line, period);
+        while self.indented_line() {
+            let line = self.peek().line();
+            match self.periodic_posting() {
+                Ok(posting) => periodic.add_posting(posting),
+                Err(message) => self.error(line, &message),
+            }
+            self.skip_line();
+        }
+        if periodic.postings().iter().filter(|posting| posting.amount().is_none()).count() > 1 {
+            self.error(line, "Only one posting may omit its amount");
+        }
+        self.journal.add_periodic_transaction(periodic);
+        Ok(())
+    }
+
+    fn periodic_posting(&mut self) -> Result<PeriodicPosting, String> {
+        let name = self.text_until(&[TokenType::Indentation, TokenType::Newline, TokenType::Semicolon]);
+        let (kind, name) = posting_kind(name.trim());
+        if name.is_empty() {
+            return Err("Expected account name".to_string());
+        }
+        let account = self.applied_account_name(name);
+        let line = self.peek().line();
+        self.check_declared(line, "account", &account);
+        let amount = if self.match_token(&TokenType::Indentation) { self.amount()? } else { None };
+        Ok(PeriodicPosting::new(self.journal.add_account(&account), kind, amount))
+    }
+
+    // Moves to the start of the next line of a block if it is indented, skipping comments.
+    fn indented_line(&mut self) -> bool {
+        while self.check(&TokenType::Indentation) {
+            match self.tokens.get(self.current + 1).map(|t| t.token_type()) {
+                Some(&TokenType::Newline) | Some(&TokenType::EOF) | None => return false,
+                _ => {}
+            }
+            self.advance();
+            if !self.check(&TokenType::Semicolon) {
+                return true;
+            }
+            self.skip_line();
+        }
+        false
+    }
+
+    fn automated_posting(&mut self) -> Result<AutomatedPosting, String> {
+        let name = self.text_until(&[TokenType::Indentation, TokenType::Newline, TokenType::Semicolon]);
+        let (kind, name) = posting_kind(name.trim());
+        if name.is_empty() {
+            return Err("Expected account name".to_string());
+        }
+        let account = self.applied_account_name(name);
+        let line = self.peek().line();
+        self.check_declared(line, "account", &account);
+        let amount = if self.match_token(&TokenType::Indentation) { self.amount_expression()? } else { None };
+        let amount = match amount {
+            Some(ref amount) if amount.commodity() == CommodityId::NONE => AutomatedAmount::Multiplier(amount.clone()),
+            Some(amount) => AutomatedAmount::Fixed(amount),
+            None => return Err(format!("Expected an amount or multiplier for {}", account)),
+        };
+        Ok(AutomatedPosting::new(self.journal.add_account(&account), kind, amount))
+    }
+
+    fn transaction(&mut self) -> Result<(), String> {
+        let line = self.peek().line();
+        let year = self.year();
+        let date = self.date(year)?;
+        // An effective date without a year shares the year of the transaction.
+        let edate = if self.match_token(&TokenType::Equals) { Some(self.date(date.year())?) } else { None };
+        self.skip_whitespace();
+
+        let status = if self.match_token(&TokenType::Star) {
+            Status::Cleared
+        }
+        else if self.match_token(&TokenType::Bang) {
+            Status::Pending
+        }
+        else {
+            Status::Unmarked
+        };
+        self.skip_whitespace();
+
+        let code = if self.match_token(&TokenType::LeftParen) {
+            let code = self.text_until(&[TokenType::RightParen]);
+            if !self.match_token(&TokenType::RightParen) {
+                return Err("Expected ')' after code".to_string());
+            }
+            self.skip_whitespace();
+            Some(code)
+        }
+        else {
+            None
+        };
+
+        let description = self.text_until_note();
+        let note = if self.match_token(&TokenType::Semicolon) { Some(self.text_until(&[TokenType::Newline])) } else { None };
+        self.skip_line();
+
+        let payee = self.payee(description.trim());
+        self.check_declared(line, "payee", &payee);
+        let mut transaction = Transaction::new(self.file, line, date, edate, status, code, description.trim().to_string());
+        transaction.set_payee(payee);
+        for applied in &self.applied {
+            if let Apply::Tag(ref tags) = *applied {
+                for (name, value) in tags {
+                    transaction.set_tag(name.clone(), value.clone());
+                }
+            }
+        }
+        self.transaction = Some(self.journal.add_transaction(transaction));
+        match note {
+            Some(note) => self.note(note.trim()),
+            None => Ok(()),
+        }
+    }
+
+    fn date(&mut self, year: i32) -> Result<NaiveDate, String> {
+        let mut date_string = String::new();
+        while self.check(&TokenType::Number) || self.check(&TokenType::Hyphen) || self.check(&TokenType::Slash) {
+            date_string.push_str(self.advance().lexeme());
+        }
+        let parts = date_string.split(&DATE_SEPARATORS[..]).collect::<Vec<&str>>();
+        if parts.len() == 2 {
+            let month = parts[0].parse().ok();
+            let day = parts[1].parse().ok();
+            return month.and_then(|month| day.and_then(|day| NaiveDate::from_ymd_opt(year, month, day)))
+                .ok_or_else(|| format!("Date is not parseable: {}", date_string));
+        }
+        parse_date(&date_string)
+    }
+
+    // The year for a date that may be written without one. Before any `year` directive this is
+    // the current year, which strict mode warns about should the date turn out to need it.
+    fn year(&mut self) -> i32 {
+        if let Some(year) = self.year {
+            return year;
+        }
+        if self.is_short_date() {
+            let line = self.peek().line();
+            self.strict(line, "Date without a year before any year directive");
+        }
+        Local::now().year()
+    }
+
+    fn is_short_date(&self) -> bool {
+        let date_string = self.tokens[self.current..].iter()
+            .take_while(|t| matches!(*t.token_type(), TokenType::Number | TokenType::Hyphen | TokenType::Slash))
+            .map(|t| t.lexeme().as_str())
+            .collect::<String>();
+        date_string.matches(&DATE_SEPARATORS[..]).count() == 1
+    }
+
+    // A note belongs to the posting on the line above it, or to the transaction when no posting
+    // has been seen yet.
+    fn note(&mut self, note: &str) -> Result<(), String> {
+        let tags = parse_tags(note)?;
+        let line = self.previous().line();
+        for (name, _) in &tags {
+            self.check_declared(line, "tag", name);
+        }
+        if let Some(posting) = self.postings.last_mut() {
+            posting.add_note(note);
+            for (name, value) in tags {
+                posting.set_tag(name, value);
+            }
+        }
+        else if let Some(transaction) = self.transaction {
+            let transaction = self.journal.transaction_mut(transaction);
+            transaction.add_note(note);
+            for (name, value) in tags {
+                transaction.set_tag(name, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn posting(&mut self) -> Result<(), String> {
+        if self.match_token(&TokenType::Star) || self.match_token(&TokenType::Bang) {
+            self.skip_whitespace();
+        }
+
+        let name = self.text_until(&[TokenType::Indentation, TokenType::Newline, TokenType::Semicolon]);
+        let (kind, name) = posting_kind(name.trim());
+        if name.is_empty() {
+            return Err("Expected account name".to_string());
+        }
+        let account = self.applied_account_name(name);
+        let line = self.peek().line();
+        self.check_declared(line, "account", &account);
+
+        let amount = if self.match_token(&TokenType::Indentation) {
+            self.amount()?
+        }
+        else {
+            None
+        };
+        let cost = self.cost(amount.as_ref())?;
+        let note = if self.match_token(&TokenType::Semicolon) { Some(self.text_until(&[TokenType::Newline])) } else { None };
+        self.skip_line();
+
+        let transaction = self.transaction.unwrap();
+        if let Some(ref cost) = cost {
+            if self.options.cost_prices {
+                let commodity = amount.as_ref().unwrap().commodity();
+                let date = self.journal.transaction(transaction).date();
+                self.journal.add_price(Price::new(date, commodity, cost.clone()));
+            }
+        }
+        let account = self.journal.add_account(&account);
+        let mut posting = Posting::new(transaction, account, amount);
+        posting.set_kind(kind);
+        posting.set_cost(cost);
+        self.postings.push(posting);
+        match note {
+            Some(note) => self.note(note.trim()),
+            None => Ok(()),
+        }
+    }
+
+    // The account a posting's account name stands for. The most recent alias that matches
+    // rewrites it once; failing that it may name a declared account, or for an `Unknown`
+    // account the payee may pick one.
+    fn account_name(&self, name: &str) -> AccountName {
+        if let Some(account) = self.aliases.iter().rev().filter_map(|alias| alias.apply(name)).next() {
+            return account;
+        }
+        if let Some(account) = self.account_aliases.get(name) {
+            return account.clone();
+        }
+        if name == "Unknown" || name.ends_with(":Unknown") {
+            // Outside a transaction, as in `bucket` or an automated posting, there is no payee.
+            if let Some(transaction) = self.transaction {
+                let payee = self.journal.transaction(transaction).payee();
+                if let Some((_, account)) = self.account_payees.iter().find(|payee_account| payee_account.0.is_match(payee)) {
+                    return account.clone();
+                }
+            }
+        }
+        name.to_string()
+    }
+
+    // The account name under any `apply account` blocks, outermost first.
+    fn applied_account_name(&self, name: &str) -> AccountName {
+        let mut segments = self.applied.iter()
+            .filter_map(|applied| match *applied {
+                Apply::Account(ref account) => Some(account.clone()),
+                Apply::Tag(_) => None,
+            })
+            .collect::<Vec<AccountName>>();
+        segments.push(self.account_name(name));
+        segments.join(":")
+    }
+
+    // The cost of each unit after `@`, or of the whole amount after `@@`.
+    fn cost(&mut self, amount: Option<&Amount>) -> Result<Option<Amount>, String> {
+        if !self.match_token(&TokenType::At) {
+            return Ok(None);
+        }
+        let total = self.match_token(&TokenType::At);
+        let amount = amount.ok_or("A cost needs an amount to apply to")?;
+        let cost = self.amount()?.ok_or("Expected a cost after '@'")?;
+        if !total {
+            return Ok(Some(cost));
+        }
+        if amount.is_zero() {
+            return Err("Cannot divide a total cost between zero units".to_string());
+        }
+        Ok(Some(Amount::new(cost.commodity(), cost.quantity() / amount.abs().quantity()).with_precision(cost.precision())))
+    }
+
+    fn amount(&mut self) -> Result<Option<Amount>, String> {
+        let amount = match self.amount_expression()? {
+            Some(amount) => amount,
+            None => return Ok(None),
+        };
+        // A bare number is in the default commodity, if there is one.
+        match self.journal.default_commodity() {
+            Some(commodity) if amount.commodity() == CommodityId::NONE => Ok(Some(amount.with_commodity(commodity.id()))),
+            _ => Ok(Some(amount)),
+        }
+    }
+
+    // Everything up to the note, which may hold either a plain amount or an expression in
+    // parentheses.
+    fn amount_expression(&mut self) -> Result<Option<Amount>, String> {
+        let start = self.current;
+        let line = self.peek().line();
+        let mut depth = 0;
+        while !self.is_at_end() && !self.check(&TokenType::Newline) {
+            match *self.peek().token_type() {
+                TokenType::LeftParen => depth += 1,
+                TokenType::RightParen => depth -= 1,
+                TokenType::Semicolon | TokenType::At if depth == 0 => break,
+                _ => {}
+            }
+            self.advance();
+        }
+        let tokens = &self.tokens[start..self.current];
+        let amount = match tokens.iter().find(|t| !t.is_whitespace()) {
+            None => return Ok(None),
+            Some(token) if *token.token_type() == TokenType::LeftParen => expression::parse(tokens)?.evaluate(&self.scope)?,
+            Some(_) => {
+                let amount = expression::parse_operand(tokens)?.evaluate(&self.scope)?;
+                // A plain amount shows how its commodity is written, and a bare number how plain
+                // numbers are.
+                let text = tokens.iter().map(|t| t.lexeme().as_str()).collect::<String>();
+                if let Ok((symbol, style)) = CommodityStyle::parse(&text) {
+                    if symbol == amount.symbol() {
+                        self.journal.learn_style(amount.commodity(), style);
+                    }
+                }
+                amount
+            }
+        };
+        if amount.commodity() != CommodityId::NONE {
+            self.check_declared(line, "commodity", amount.symbol());
+        }
+        Ok(Some(amount))
+    }
+
+    // Free text up to a note. A semicolon only starts a note when it follows whitespace.
+    fn text_until_note(&mut self) -> String {
+        let mut text = String::new();
+        while !self.is_at_end() && !self.check(&TokenType::Newline) {
+            if self.check(&TokenType::Semicolon) && (text.is_empty() || self.previous().is_whitespace()) {
+                break;
+            }
+            text.push_str(self.advance().lexeme());
+        }
+        text
+    }
+
+    fn text_until(&mut self, token_types: &[TokenType]) -> String {
+        let mut text = String::new();
+        while !self.is_at_end() && !token_types.contains(self.peek().token_type()) {
+            if self.check(&TokenType::Newline) {
+                break;
+            }
+            text.push_str(self.advance().lexeme());
+        }
+        text
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_whitespace() {
+            self.advance();
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while !self.is_at_end() && !self.check(&TokenType::Newline) {
+            self.advance();
+        }
+        self.match_token(&TokenType::Newline);
+    }
+
+    // Recover from an error by discarding the rest of the line, as a journal has no other
+    // statement terminator, unless the line was already read to its end.
+    fn synchronize(&mut self, start: usize) {
+        if self.current == start || *self.previous().token_type() != TokenType::Newline {
+            self.skip_line();
+        }
+    }
+
+    fn match_token(&mut self, token_type: &TokenType) -> bool {
+        if self.check(token_type) {
+            self.advance();
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    fn check(&self, token_type: &TokenType) -> bool {
+        self.peek().token_type() == token_type
+    }
+
+    fn advance(&mut self) -> &Token {
+        if !self.is_at_end() {
+            self.current += 1;
+        }
+        self.previous()
+    }
+
+    fn is_at_end(&self) -> bool {
+        *self.peek().token_type() == TokenType::EOF
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.current]
+    }
+
+    fn previous(&self) -> &Token {
+        &self.tokens[self.current - 1]
+    }
+}
+
+const DATE_SEPARATORS: [char; 3] = ['-', '/', '.'];
+
+#[derive(Debug, Clone)]
+struct Assertion {
+    file: Option<FileId>,
+    line: usize,
+    // An `assert` that fails is an error, and a `check` a warning.
+    level: Level,
+    text: String,
+    expression: ValueExpr,
+    target: Target,
+}
+
+// What an assertion is evaluated for.
+#[derive(Debug, Clone)]
+enum Target {
+    Journal,
+    Account(AccountName),
+    Tag(TagName),
+}
+
+// The value of a tag as seen by a value expression. A tag without one has an empty string.
+fn tag_value(value: Option<&TagValue>) -> Value {
+    match value {
+        Some(TagValue::String(s)) => Value::String(s.clone()),
+        Some(TagValue::Date(date)) => Value::Date(*date),
+        Some(TagValue::Amount(amount)) => Value::Amount(amount.clone()),
+        None => Value::String(String::new()),
+    }
+}
+
+// A timeclock check-in waiting for its check-out.
+#[derive(Debug, Clone)]
+struct ClockIn {
+    file: Option<FileId>,
+    line: usize,
+    time: NaiveDateTime,
+    account: AccountName,
+    description: String,
+    payee: String,
+}
+
+// The date and time at the start of a timeclock line, and the rest of it. Seconds are optional.
+fn timestamp(argument: &str) -> Result<(NaiveDateTime, &str), String> {
+    let mut words = argument.splitn(3, char::is_whitespace);
+    let date = parse_date(words.next().unwrap_or_default())?;
+    let time = words.next().ok_or("Expected a time")?;
+    let time = NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+        .map_err(|_| format!("Time is not parseable: {}", time))?;
+    Ok((date.and_time(time), words.next().unwrap_or_default().trim()))
+}
+
+// `(Account)` is a virtual posting and `[Account]` a balanced virtual one.
+fn posting_kind(name: &str) -> (PostingKind, &str) {
+    if name.len() > 1 && name.starts_with('(') && name.ends_with(')') {
+        (PostingKind::Virtual, name[1..name.len() - 1].trim())
+    }
+    else if name.len() > 1 && name.starts_with('[') && name.ends_with(']') {
+        (PostingKind::BalancedVirtual, name[1..name.len() - 1].trim())
+    }
+    else {
+        (PostingKind::Real, name)
+    }
+}
+
+pub fn parse_date(date_string: &str) -> Result<NaiveDate, String> {
+    ["%Y-%m-%d", "%Y/%m/%d", "%Y.%m.%d"].iter()
+        .filter_map(|format| NaiveDate::parse_from_str(date_string, format).ok())
+        .next()
+        .ok_or_else(|| format!("Date is not parseable: {}", date_string))
+}
+
+// Tags are written `:one:two:`. A word ending in a colon is a key whose value is the rest of the
+// line; with a double colon the value is evaluated as a date in brackets or an amount.
+fn parse_tags(note: &str) -> Result<Vec<(TagName, Option<TagValue>)>, String> {
+    let mut tags = Vec::new();
+    for line in note.lines() {
+        let mut rest = line.trim_start();
+        while !rest.is_empty() {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let word = &rest[..end];
+            let remainder = rest[end..].trim();
+            if word.len() > 1 && word.starts_with(':') && word.ends_with(':') {
+                tags.extend(word.split(':').filter(|tag| !tag.is_empty()).map(|tag| (tag.to_string(), None)));
+            }
+            else if word.len() > 2 && word.ends_with("::") {
+                tags.push((word[..word.len() - 2].to_string(), Some(parse_typed_value(remainder)?)));
+                break;
+            }
+            else if word.len() > 1 && word.ends_with(':') && !word.starts_with(':') {
+                let value = if remainder.is_empty() { None } else { Some(TagValue::String(remainder.to_string())) };
+                tags.push((word[..word.len() - 1].to_string(), value));
+                break;
+            }
+            rest = rest[end..].trim_start();
+        }
+    }
+    Ok(tags)
+}
+
+fn parse_typed_value(value: &str) -> Result<TagValue, String> {
+    if value.starts_with('[') && value.ends_with(']') {
+        return Ok(TagValue::Date(parse_date(&value[1..value.len() - 1])?));
+    }
+    let tokens = lexer::lex(value);
+    let amount = if value.starts_with('(') {
+        expression::parse(&tokens)?.evaluate(&Scope::new())?
+    }
+    else {
+        expression::parse_amount(&tokens)?
+    };
+    Ok(TagValue::Amount(amount))
 }
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
-    // #[test]
-    // fn test_float() {
-    //     assert_eq!("04".parse::<f64>(), Ok(4f64));
-    //     assert_eq!(".04".parse::<f64>(), Ok(0.04));
-    // }
-
-
-    // #[should_panic]
-    // fn test_panic_on_newline_with_indentation() {
-        // parse(vec![TokenType::Indentation]);
-    // }
+    use super::*;
+    use error::Level;
+    use lexer::lex;
+    use std::env;
+    use std::fs;
+
+    fn amount(number: &str, commodity: &str) -> Amount {
+        Amount::parse(CommodityId::intern(commodity), number).unwrap()
+    }
+
+    #[test]
+    fn test_parse_transaction() {
+        let journal = parse(lex("2013-01-01 * Beginning Balance\n  Assets:Cash  $1\n  Equity:Opening Balance\n"));
+        let postings = journal.postings();
+        assert_eq!(postings.len(), 2);
+        assert_eq!(journal.transaction(postings[0].transaction()).description(), "Beginning Balance");
+        assert_eq!(journal.transaction(postings[0].transaction()).date(), NaiveDate::from_ymd_opt(2013, 1, 1).unwrap());
+        assert_eq!(journal.account(postings[0].account()).name(), "Assets:Cash");
+        assert_eq!(postings[0].amount(), Some(&amount("1", "$")));
+        assert_eq!(journal.account(postings[1].account()).name(), "Equity:Opening Balance");
+        assert_eq!(postings[1].amount(), Some(&amount("-1", "$")));
+    }
+
+    #[test]
+    fn test_bad_header_skips_postings() {
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex("2013/02/30 Lunch\n  Expenses:Food  $10\n  Assets:Cash\n\n2013/03/01 Tea\n  Expenses:Food  $2\n  Assets:Cash\n"));
+        let diagnostics = parser.diagnostics.iter().map(|d| (d.line(), d.message().clone())).collect::<Vec<(usize, String)>>();
+        assert_eq!(diagnostics, vec![(1, "Date is not parseable: 2013/02/30".to_string())]);
+        assert_eq!(parser.journal.postings().len(), 2);
+
+        let journal = parse(lex("2013/02/30 Lunch\n  Expenses:Food  $10\n  Assets:Cash\n"));
+        assert_eq!(journal.diagnostics().len(), 1);
+        assert_eq!(journal.diagnostics()[0].line(), 1);
+    }
+
+    #[test]
+    fn test_parse_amount_expression() {
+        let journal = parse(lex("2013/01/04 Dinner\n  Expenses:Shared  ($120 / 3)  ; split three ways\n  Assets:Cash\n"));
+        let postings = journal.postings();
+        assert_eq!(postings[0].amount(), Some(&amount("40", "$")));
+    }
+
+    #[test]
+    fn test_parse_skips_bad_lines() {
+        let journal = parse(lex("2013/01/04 Dinner\n  Expenses:Shared  ($120 + 3 EUR)\n  Assets:Cash  $-40\n"));
+        let postings = journal.postings();
+        assert_eq!(postings.len(), 1);
+        assert_eq!(postings[0].amount(), Some(&amount("-40", "$")));
+    }
+
+    #[test]
+    fn test_parse_tags() {
+        let journal = parse(lex("2013/01/04 Lunch  ; :reimbursable:work:\n    ; Receipt: 2013-01-04.pdf\n  Expenses:Food  $10  ; :food:\n    ; Due:: [2013-02-01]\n  Assets:Cash  ; Paid:: $10\n"));
+        let postings = journal.postings();
+        let food = postings[0].tags();
+        assert_eq!(food.get("reimbursable"), Some(&None));
+        assert_eq!(food.get("food"), Some(&None));
+        assert_eq!(food.get("Receipt"), Some(&Some(TagValue::String("2013-01-04.pdf".to_string()))));
+        assert_eq!(food.get("Due"), Some(&Some(TagValue::Date(NaiveDate::from_ymd_opt(2013, 2, 1).unwrap()))));
+        assert_eq!(postings[0].note().unwrap(), ":food:\nDue:: [2013-02-01]");
+
+        let cash = postings[1].tags();
+        assert_eq!(cash.get("work"), Some(&None));
+        assert_eq!(cash.get("food"), None);
+        assert_eq!(cash.get("Paid"), Some(&Some(TagValue::Amount(amount("10", "$")))));
+        assert!(journal.transaction(postings[1].transaction()).tags().get("Paid").is_none());
+    }
+
+    #[test]
+    fn test_account_directive() {
+        let journal = parse(lex("account Assets:Checking\n    note Main account\n    ; a comment\n    alias checking\n    default\naccount Expenses:Food\n    payee ^Whole Foods\n    check commodity == \"$\"\n\n2013/01/04 Whole Foods Market\n  Expenses:Unknown  $10\n  checking\n\n2013/01/05 Lunch\n  Expences:Food  $10\n  Assets:Checking\n"));
+        let postings = journal.postings();
+        assert_eq!(journal.account(postings[0].account()).name(), "Expenses:Food");
+        assert_eq!(journal.account(postings[1].account()).name(), "Assets:Checking");
+
+        let checking = journal.account(journal.find_account("Assets:Checking").unwrap());
+        assert!(checking.is_declared());
+        assert!(checking.is_default());
+        assert_eq!(checking.note().unwrap(), "Main account");
+        assert_eq!(checking.aliases(), &vec!["checking".to_string()]);
+        assert_eq!(journal.account(journal.find_account("Expenses:Food").unwrap()).checks(), &vec!["commodity == \"$\"".to_string()]);
+
+        let undeclared = journal.undeclared_accounts().iter().map(|a| a.name().clone()).collect::<Vec<String>>();
+        assert_eq!(undeclared, vec!["Expences:Food"]);
+    }
+
+    #[test]
+    fn test_alias_directive() {
+        let journal = parse(lex("alias Checking=Assets:Checking\nalias /^Bank\\b/=Assets:BofA\nalias /^(\\w+):Old$/=\\1:New\n\n2013/01/04 Lunch\n  Bank:Checking  $10\n  Checking  $-1\n  Checking:Sub  $-1\n  Checkings  $-1\n  Cash:Old\n\nend aliases\n2013/01/05 Lunch\n  Bank:Checking  $10\n  Checking\n"));
+        let names = journal.postings().iter().map(|p| journal.account(p.account()).name().clone()).collect::<Vec<String>>();
+        assert_eq!(names, vec![
+            "Assets:BofA:Checking", "Assets:Checking", "Assets:Checking:Sub", "Checkings", "Cash:New",
+            "Bank:Checking", "Checking",
+        ]);
+    }
+
+    #[test]
+    fn test_alias_options() {
+        let mut options = Options::new();
+        options.add_alias("/^Bank/=Assets:BofA").unwrap();
+        assert!(options.add_alias("Bank").is_err());
+        assert!(options.add_alias("/(/=x").is_err());
+
+        let mut parser = Parser::new(&options);
+        parser.parse(lex("end aliases\n2013/01/04 Lunch\n  Bank:Checking  $10\n  Assets:Cash\n"));
+        assert_eq!(parser.journal.account(parser.journal.postings()[0].account()).name(), "Assets:BofA:Checking");
+    }
+
+    #[test]
+    fn test_apply_directives() {
+        let journal = parse(lex("apply account Household\napply tag work\napply account Kitchen\n2013/01/04 Pans\n  Expenses:Pans  $10\n  Assets:Cash\nend apply account\napply tag Client: Acme\n2013/01/05 Lunch\n  Expenses:Food  $10\n  Assets:Cash\nend apply\nend apply tag\nend apply account\n2013/01/06 Tea\n  Expenses:Tea  $1\n  Assets:Cash\n"));
+        let postings = journal.postings();
+        let names = postings.iter().map(|p| journal.account(p.account()).name().clone()).collect::<Vec<String>>();
+        assert_eq!(names, vec![
+            "Household:Kitchen:Expenses:Pans", "Household:Kitchen:Assets:Cash",
+            "Household:Expenses:Food", "Household:Assets:Cash",
+            "Expenses:Tea", "Assets:Cash",
+        ]);
+        assert!(postings[0].tags().contains_key("work"));
+        assert_eq!(postings[2].tags().get("Client"), Some(&Some(TagValue::String("Acme".to_string()))));
+        assert!(postings[4].tags().is_empty());
+    }
+
+    #[test]
+    fn test_mismatched_end_apply() {
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex("apply tag work\nend apply account\nend apply tag\nend apply tag\n"));
+        let messages = parser.diagnostics.iter().map(|d| d.message().clone()).collect::<Vec<String>>();
+        assert_eq!(messages, vec![
+            "'end apply account' does not match 'apply tag'",
+            "'end apply tag' without a matching 'apply'",
+        ]);
+    }
+
+    #[test]
+    fn test_bucket() {
+        let journal = parse(lex("bucket Assets:Checking\n2013/01/04 Lunch\n  Expenses:Food  $10\n\n2013/01/05 Tea\n  Expenses:Tea  $1\n  Assets:Cash\n"));
+        let names = journal.postings().iter().map(|p| journal.account(p.account()).name().clone()).collect::<Vec<String>>();
+        assert_eq!(names, vec!["Expenses:Food", "Assets:Checking", "Expenses:Tea", "Assets:Cash"]);
+    }
+
+    #[test]
+    fn test_unknown_account_outside_transaction() {
+        let declarations = "account Expenses:Food\n    payee ^Shop\n";
+        for journal in &["bucket Unknown\n", "i 2013/01/05 09:00:00 Unknown\n", "= /Income/\n  (Expenses:Unknown)  0.1\n", "~ Monthly\n  Expenses:Unknown  $1\n"] {
+            let mut parser = Parser::new(&Options::new());
+            parser.parse(lex(&format!("{}{}", declarations, journal)));
+            assert!(parser.journal.postings().is_empty(), "{}", journal);
+        }
+    }
+
+    #[test]
+    fn test_year_directive() {
+        let journal = parse(lex("year 2012\n01/15 Lunch\n  Expenses:Food  $10\n  Assets:Cash\n\nY 2011\n2013/12/31=01-02 Tea\n  Expenses:Tea  $1\n  Assets:Cash\n02.03 Coffee\n  Expenses:Coffee  $1\n  Assets:Cash\n"));
+        let postings = journal.postings();
+        assert_eq!(journal.transaction(postings[0].transaction()).date(), NaiveDate::from_ymd_opt(2012, 1, 15).unwrap());
+        assert_eq!(journal.transaction(postings[2].transaction()).date(), NaiveDate::from_ymd_opt(2013, 12, 31).unwrap());
+        assert_eq!(journal.transaction(postings[2].transaction()).edate(), NaiveDate::from_ymd_opt(2013, 1, 2));
+        assert_eq!(journal.transaction(postings[4].transaction()).date(), NaiveDate::from_ymd_opt(2011, 2, 3).unwrap());
+    }
+
+    #[test]
+    fn test_styles_from_usage() {
+        let journal = parse(lex("commodity GBP\n    format GBP 1,000.0\n\n2013/01/04 Lunch\n  Expenses:Food  $10\n  Expenses:Food  1.000,5 EUR\n  Expenses:Food  GBP5\n  Assets:Cash\n\n2013/01/05 Rent\n  Expenses:Rent  $1,500.00\n  Assets:Cash\n"));
+        let postings = journal.postings();
+        let shown = postings.iter().map(|p| journal.format_amount(p.amount().unwrap())).collect::<Vec<String>>();
+        assert_eq!(shown, vec!["$10.00", "1.000,5 EUR", "GBP 5.0", "$-10.00", "-1.000,5 EUR", "GBP -5.0", "$1,500.00", "$-1,500.00"]);
+        assert_eq!(journal.style(CommodityId::intern("$")), CommodityStyle::new(true, false, '.', Some(','), 2));
+    }
+
+    #[test]
+    fn test_commodity_directive() {
+        let journal = "commodity $1,000.00\ncommodity EUR\n    note Euro\n    format 1.000,0 EUR\n    format $1.00\n    nomarket\n";
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex(journal));
+        let journal = &parser.journal;
+        assert_eq!(journal.format_amount(&amount("-1234.5", "$")), "$-1,234.50");
+        assert_eq!(journal.format_amount(&amount("1234.56", "EUR")), "1.234,6 EUR");
+        let euro = journal.commodity(CommodityId::intern("EUR")).unwrap();
+        assert_eq!(euro.note(), Some(&"Euro".to_string()));
+        assert!(euro.is_nomarket());
+        assert_eq!(parser.diagnostics.len(), 1);
+        assert_eq!(parser.diagnostics[0].line(), 5);
+    }
+
+    #[test]
+    fn test_unbalanced_transaction() {
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex("; Lunch\n\n2013/01/04 Lunch\n  Expenses:Food  $10\n  Assets:Cash  $-9\n"));
+        assert_eq!(parser.diagnostics.len(), 1);
+        assert_eq!(parser.diagnostics[0].line(), 3);
+        assert_eq!(parser.diagnostics[0].message(), "Transaction does not balance: $1 left over");
+    }
+
+    #[test]
+    fn test_elided_amounts_are_never_left_empty() {
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex("2013/01/04 Lunch\n  Expenses:Food  $10\n  Assets:Cash\n  Assets:Bank\n\n2013/01/05 Swap\n  Assets:Cash  $5\n  Assets:Cash  $-5\n  Equity\n"));
+        assert_eq!(parser.diagnostics.iter().map(|d| (d.line(), d.message().as_str())).collect::<Vec<(usize, &str)>>(), vec![(1, "Only one posting may omit its amount")]);
+        let postings = parser.journal.postings();
+        assert_eq!(postings.len(), 4);
+        assert!(postings.iter().all(|p| p.amount().is_some()));
+        assert!(postings[3].amount().unwrap().is_zero());
+    }
+
+    #[test]
+    fn test_price_directive() {
+        let journal = parse(lex("P 2013-01-31 AAPL $456.78\nP 2013/02/28 12:00:00 AAPL $441\nP 2013-02-28 EUR 1.35 USD\n"));
+        let prices = journal.prices();
+        let date = NaiveDate::from_ymd_opt(2013, 2, 1).unwrap();
+        assert_eq!(prices.price("AAPL", "$", date).unwrap().price(), &amount("456.78", "$"));
+        assert_eq!(prices.price("AAPL", "$", date.with_month(3).unwrap()).unwrap().price(), &amount("441", "$"));
+        assert_eq!(prices.price("EUR", "USD", date.with_month(3).unwrap()).unwrap().price().symbol(), "USD");
+    }
+
+    #[test]
+    fn test_balance_is_exact() {
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex("2013/01/04 Snacks\n  Expenses:Food  $0.10\n  Expenses:Food  $0.20\n  Assets:Cash  $-0.30\n\n2013/01/05 Split\n  Expenses:Food  $0.333\n  Assets:Cash  $-0.33\n"));
+        let messages = parser.diagnostics.iter().map(|d| d.message().clone()).collect::<Vec<String>>();
+        assert_eq!(messages, vec!["Transaction does not balance: $0.003 left over".to_string()]);
+    }
+
+    #[test]
+    fn test_costs() {
+        let journal = "2013/01/31 Buy\n  Assets:Brokerage  10 AAPL @ $456.78\n  Assets:Cash\n\n2013/02/28 Sell\n  Assets:Brokerage  -10 AAPL @@ $4410  ; at a loss\n  Assets:Cash  $4410\n";
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex(journal));
+        assert!(parser.diagnostics.is_empty());
+        let postings = parser.journal.postings();
+        assert_eq!(postings[0].cost(), Some(&amount("456.78", "$")));
+        assert_eq!(postings[1].amount(), Some(&amount("-4567.8", "$")));
+        assert_eq!(postings[2].cost(), Some(&amount("441", "$")));
+        assert_eq!(postings[2].note(), Some(&"at a loss".to_string()));
+        assert!(parser.journal.prices().is_empty());
+
+        let mut options = Options::new();
+        options.set_cost_prices(true);
+        let mut parser = Parser::new(&options);
+        parser.parse(lex(journal));
+        let latest = parser.journal.prices().price("AAPL", "$", NaiveDate::from_ymd_opt(2013, 3, 1).unwrap()).unwrap();
+        assert_eq!(latest.price(), &amount("441", "$"));
+    }
+
+    #[test]
+    fn test_default_commodity_and_conversions() {
+        let journal = "D $1,000.00\nN $\nC 1h = 60m\n\n2013/01/04 Work\n  Projects:Acme  1h\n  Projects:Acme  30m\n  Time:Billable  -90m\n\n2013/01/05 Lunch\n  Expenses:Food  12\n  Assets:Cash\n";
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex(journal));
+        assert!(parser.diagnostics.is_empty());
+        let dollar = parser.journal.default_commodity().unwrap();
+        assert_eq!(dollar.symbol(), "$");
+        assert!(dollar.is_nomarket());
+        let postings = parser.journal.postings();
+        assert_eq!(parser.journal.add_amounts(postings[0].amount().unwrap(), postings[1].amount().unwrap()),
+                   Some(amount("1.5", "h")));
+        assert_eq!(parser.journal.format_amount(postings[4].amount().unwrap()), "$-12.00");
+
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex("C 1h = 60m\n2013/01/04 Work\n  Projects:Acme  1h\n  Time:Billable  -50m\n"));
+        assert_eq!(parser.diagnostics[0].message(), "Transaction does not balance: 10m left over");
+    }
+
+    #[test]
+    fn test_automated_transactions() {
+        let journal = "= /^Income:Salary/\n  (Assets:Savings)  0.1\n\n= expenses and tag:shared\n  [Assets:Receivable]  0.5\n  [Expenses:Food]  -0.5\n  (Budget:Fees)  $1\n\n2013/01/31 Payday\n  Assets:Checking  $2,000.00\n  Income:Salary\n\n2013/02/01 Dinner  ; :shared:\n  Expenses:Food  $40\n  Assets:Cash\n";
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex(journal));
+        assert!(parser.diagnostics.is_empty());
+        let generated = parser.journal.postings().iter()
+            .filter(|posting| posting.is_generated())
+            .map(|posting| (parser.journal.account(posting.account()).name().clone(), posting.kind(), posting.amount().unwrap().clone()))
+            .collect::<Vec<(AccountName, PostingKind, Amount)>>();
+        assert_eq!(generated, vec![
+            ("Assets:Savings".to_string(), PostingKind::Virtual, amount("-200", "$")),
+            ("Assets:Receivable".to_string(), PostingKind::BalancedVirtual, amount("20", "$")),
+            ("Expenses:Food".to_string(), PostingKind::BalancedVirtual, amount("-20", "$")),
+            ("Budget:Fees".to_string(), PostingKind::Virtual, amount("1", "$")),
+        ]);
+        assert_eq!(parser.journal.postings().len(), 8);
+    }
+
+    #[test]
+    fn test_virtual_postings() {
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex("2013/01/31 Payday\n  Assets:Checking  $100\n  Income:Salary\n  (Budget:Savings)  $10\n  [Budget:Food]  $30\n  [Budget:Unallocated]\n"));
+        assert!(parser.diagnostics.is_empty());
+        let postings = parser.journal.postings();
+        assert_eq!(postings[1].amount(), Some(&amount("-100", "$")));
+        assert_eq!(postings[2].kind(), PostingKind::Virtual);
+        assert_eq!(parser.journal.account(postings[2].account()).name(), "Budget:Savings");
+        assert_eq!(postings[4].amount(), Some(&amount("-30", "$")));
+    }
+
+    #[test]
+    fn test_periodic_transactions() {
+        let journal = "~ Monthly  ; budget\n  Expenses:Rent  $1500\n  ; the flat\n  Assets\n\n~ every 2 weeks from 2013/01/04\n  Expenses:Food  $100\n  Assets:Cash\n  Assets:Checking\n\n2013/01/01 Rent\n  Expenses:Rent  $1500\n  Assets:Checking\n";
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex(journal));
+        assert_eq!(parser.diagnostics.len(), 1);
+        assert_eq!(parser.diagnostics[0].line(), 6);
+        assert_eq!(parser.journal.postings().len(), 2);
+
+        let periodic = parser.journal.periodic_transactions()[0].clone();
+        assert_eq!(periodic.period().to_string(), "Monthly");
+        assert_eq!(periodic.postings().len(), 2);
+        assert!(periodic.postings()[1].amount().is_none());
+        let begin = NaiveDate::from_ymd_opt(2013, 1, 1).unwrap();
+        let journal = &parser.journal;
+        let occurrences = periodic.expand(journal, begin, begin.with_month(3).unwrap());
+        assert_eq!(occurrences.len(), 2);
+        let february = &occurrences[1];
+        assert_eq!(february.date(), begin.with_month(2).unwrap());
+        assert_eq!(february.postings().len(), 2);
+        let rent = &february.postings()[0];
+        assert_eq!(journal.account(rent.account()).name(), "Expenses:Rent");
+        let assets = &february.postings()[1];
+        assert_eq!(assets.amount(), Some(&Amount::new(CommodityId::intern("$"), Quantity::from_integer(BigInt::from(-1500)))));
+        assert_eq!(journal.postings().len(), 2);
+    }
+
+    #[test]
+    fn test_bad_period_skips_postings() {
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex("~ Fortnightly\n  Expenses:Rent  $500\n  Assets:Checking\n\n2013/01/04 Lunch\n  Expenses:Food  $10\n  Assets:Cash\n"));
+        let diagnostics = parser.diagnostics.iter().map(|d| (d.line(), d.message().clone())).collect::<Vec<(usize, String)>>();
+        assert_eq!(diagnostics, vec![(1, "Expected a period such as 'monthly', found 'Fortnightly'".to_string())]);
+        assert!(parser.journal.periodic_transactions().is_empty());
+        assert_eq!(parser.journal.postings().len(), 2);
+    }
+
+    #[test]
+    fn test_timeclock() {
+        let timeclock = "i 2013/01/05 09:00:00 Client:Project  Design review\no 2013/01/05 12:30:00\ni 2013/01/05 12:00 Client:Other\ni 2013/01/05 13:00:00 Client:Project\ni 2013/01/05 14:00:00 Client:Other\no 2013/01/05 17:15:00\no 2013/01/05 18:00:00\ni 2013/01/06 23:00:00 Client:Project\no 2013/01/07 01:00:00\ni 2013/01/08 09:00:00 Client:Project\n";
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex(timeclock));
+        let sessions = parser.journal.postings().iter()
+            .map(|p| (parser.journal.transaction(p.transaction()).date().day(), parser.journal.transaction(p.transaction()).description().clone(), parser.journal.account(p.account()).name().clone(), p.amount().unwrap().clone()))
+            .collect::<Vec<(u32, String, AccountName, Amount)>>();
+        assert_eq!(sessions, vec![
+            (5, "Design review".to_string(), "Client:Project".to_string(), amount("3.5", "h")),
+            (5, "Client:Project".to_string(), "Client:Project".to_string(), amount("4.25", "h")),
+            (6, "Client:Project".to_string(), "Client:Project".to_string(), amount("2", "h")),
+        ]);
+        assert_eq!(parser.journal.postings()[0].kind(), PostingKind::Virtual);
+        let lines = parser.diagnostics.iter().map(|d| d.line()).collect::<Vec<usize>>();
+        assert_eq!(lines, vec![3, 5, 7, 10]);
+        assert_eq!(parser.diagnostics[3].message(), "Check-in to Client:Project is never checked out");
+    }
+
+    #[test]
+    fn test_strict_and_pedantic() {
+        let journal = "account Assets:Cash\naccount Expenses:Food\ncommodity $\npayee Grocer\ntag receipt\n\n2013/01/04 Grocer\n  Expences:Food  $10  ; receipt: yes\n  Assets:Cash\n\n2013/01/05 Grocer\n  Expences:Food  10 EUR  ; project: home\n  Assets:Cash\n\n2013/01/06 Baker\n  Expenses:Food  $5\n  Assets:Cash\n";
+        let diagnostics = |options: &Options| {
+            let mut parser = Parser::new(options);
+            parser.parse(lex(journal));
+            parser.diagnostics.iter().map(|d| (d.level(), d.line(), d.message().clone())).collect::<Vec<(Level, usize, String)>>()
+        };
+        assert!(diagnostics(&Options::new()).is_empty());
+
+        let mut options = Options::new();
+        options.set_strict(true);
+        assert_eq!(diagnostics(&options), vec![
+            (Level::Warning, 8, "Unknown account 'Expences:Food'".to_string()),
+            (Level::Warning, 12, "Unknown commodity 'EUR'".to_string()),
+            (Level::Warning, 12, "Unknown tag 'project'".to_string()),
+            (Level::Warning, 15, "Unknown payee 'Baker'".to_string()),
+        ]);
+
+        let mut options = Options::new();
+        options.set_pedantic(true);
+        let pedantic = diagnostics(&options);
+        assert_eq!(pedantic.len(), 4);
+        assert!(pedantic.iter().all(|d| d.0 == Level::Error));
+    }
+
+    #[test]
+    fn test_assert_and_check() {
+        let journal = "account Expenses:Food\n    check commodity == \"$\"\n    assert abs(amount) < 100\n\n2013/01/04 Lunch\n  Expenses:Food  $10\n  Assets:Cash\n\n2013/01/05 Market\n  Expenses:Food  12 EUR\n  Assets:Cash\n\nassert account(\"Assets:Cash\").total >= 0\ncheck account(\"Expenses\").total > 0\nassert account(\"Expenses:Food\").total +\nassert amount > 0\n";
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex(journal));
+        parser.check_assertions();
+        let diagnostics = parser.diagnostics.iter().map(|d| (d.level(), d.line(), d.message().clone())).collect::<Vec<(Level, usize, String)>>();
+        assert_eq!(diagnostics, vec![
+            (Level::Error, 15, "Expected an expression".to_string()),
+            (Level::Warning, 2, "Check failed for Market on 2013/01/05: commodity == \"$\"".to_string()),
+            (Level::Error, 13, "Assertion failed: account(\"Assets:Cash\").total >= 0".to_string()),
+            (Level::Error, 16, "'amount' is only known within an account block".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_payee_and_tag_declarations() {
+        let journal = "payee Whole Foods\n    alias ^WHOLEFDS\n    alias ^WFM \\d+\n    url wholefoods.com\ntag Receipt\n    assert value =~ /^R-[0-9]+$/\n    check payee == \"Whole Foods\"\n\n2013/01/04 WHOLEFDS MKT #123\n  ; Receipt: R-1\n  Expenses:Food  $10\n  Assets:Cash\n\n2013/01/05 WFM 456\n  Expenses:Food  $12  ; Receipt: 456\n  Assets:Cash\n\n2013/01/06 Baker\n  Expenses:Food  $5  ; Receipt: R-2\n  Assets:Cash\n";
+        let mut options = Options::new();
+        options.set_strict(true);
+        let mut parser = Parser::new(&options);
+        parser.parse(lex(journal));
+        parser.check_assertions();
+        let payees = parser.journal.postings().iter().map(|p| parser.journal.transaction(p.transaction()).payee().as_str()).collect::<Vec<&str>>();
+        assert_eq!(payees, vec!["Whole Foods", "Whole Foods", "Whole Foods", "Whole Foods", "Baker", "Baker"]);
+        assert_eq!(parser.journal.transaction(parser.journal.postings()[0].transaction()).description(), "WHOLEFDS MKT #123");
+        assert_eq!(parser.journal.declared_payees().next().unwrap().aliases().len(), 2);
+        let diagnostics = parser.diagnostics.iter().map(|d| (d.level(), d.line(), d.message().clone())).collect::<Vec<(Level, usize, String)>>();
+        assert_eq!(diagnostics, vec![
+            (Level::Error, 4, "Unknown payee subdirective 'url'".to_string()),
+            (Level::Warning, 11, "Unknown account 'Expenses:Food'".to_string()),
+            (Level::Warning, 11, "Unknown commodity '$'".to_string()),
+            (Level::Warning, 12, "Unknown account 'Assets:Cash'".to_string()),
+            (Level::Warning, 18, "Unknown payee 'Baker'".to_string()),
+            (Level::Error, 6, "Assertion failed for Whole Foods on 2013/01/05: value =~ /^R-[0-9]+$/".to_string()),
+            (Level::Warning, 7, "Check failed for Baker on 2013/01/06: payee == \"Whole Foods\"".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_define_directive() {
+        let journal = "define tax_rate=0.0725\ndefine savings=(tax_rate * 2)\n= /^Income/\n  (Assets:Savings)  savings\n\n2013/01/04 Shop\n  Expenses:Food  $100.00\n  Expenses:Tax  ($100 * tax_rate)\n  Assets:Cash\n\napply account Personal\ndefine tax_rate=0.5\n2013/01/05 Shop\n  Expenses:Tax  ($10 * tax_rate)\n  Assets:Cash\nend apply\ndefine tax_rate=0.1\n\n2013/01/06 Salary\n  Assets:Cash  $1000\n  Income:Salary  ($-1000 * bonus)\n  Income:Salary\n";
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex(journal));
+        let postings = parser.journal.postings();
+        let format = |p: &Posting| parser.journal.format_amount(p.amount().unwrap());
+        assert_eq!(format(&postings[1]), "$7.25");
+        assert_eq!(format(&postings[3]), "$5.00");
+        assert_eq!(parser.journal.account(postings[3].account()).name(), "Personal:Expenses:Tax");
+        let savings = postings.iter().find(|p| p.is_generated()).unwrap();
+        assert_eq!(format(savings), "$-145.00");
+        let diagnostics = parser.diagnostics.iter().map(|d| (d.level(), d.line(), d.message().clone())).collect::<Vec<(Level, usize, String)>>();
+        assert_eq!(diagnostics, vec![
+            (Level::Warning, 17, "Variable 'tax_rate' is already defined".to_string()),
+            (Level::Error, 21, "Undefined variable 'bonus'".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_short_date_without_year() {
+        let journal = "2013/01/14 Breakfast\n  Expenses:Food  $10\n  Assets:Cash\n01/15 Lunch\n  Expenses:Food  $10\n  Assets:Cash\n";
+        let mut parser = Parser::new(&Options::new());
+        parser.parse(lex(journal));
+        assert!(parser.diagnostics.is_empty());
+        assert_eq!(parser.journal.transaction(parser.journal.postings()[2].transaction()).date().year(), Local::now().year());
+
+        let mut options = Options::new();
+        options.set_strict(true);
+        let mut parser = Parser::new(&options);
+        let declarations = "account Expenses:Food\naccount Assets:Cash\ncommodity $\npayee Breakfast\npayee Lunch\n";
+        parser.parse(lex(&format!("{}{}", declarations, journal)));
+        assert_eq!(parser.diagnostics.len(), 1);
+        assert_eq!(parser.diagnostics[0].level(), Level::Warning);
+        assert_eq!(parser.diagnostics[0].line(), 9);
+    }
+
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ledger-{}-{}", name, ::std::process::id()));
+        for &(path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = write_files("include", &[
+            ("main.journal", "include 2013.journal\ninclude prices/*.db\n"),
+            ("2013.journal", "2013/01/04 Lunch\n  Expenses:Food  $10\n  Assets:Cash\n"),
+            ("prices/a.db", "2013/01/05 Coffee\n  Expenses:Food  $2\n  Assets:Cash\n"),
+            ("prices/b.db", "2013/01/06 Tea\n  Expenses:Food  $3\n  Assets:Cash  ($3 + 1 EUR)\n"),
+        ]);
+        let mut parser = Parser::new(&Options::new());
+        parser.parse_file(&dir.join("main.journal")).unwrap();
+
+        let files = parser.journal.postings().iter()
+            .map(|p| parser.journal.transaction(p.transaction()).file().map(|id| parser.journal.file(id)).unwrap().strip_prefix(&dir).unwrap().to_path_buf())
+            .collect::<Vec<PathBuf>>();
+        assert_eq!(files, vec![
+            PathBuf::from("2013.journal"), PathBuf::from("2013.journal"),
+            PathBuf::from("prices/a.db"), PathBuf::from("prices/a.db"),
+            PathBuf::from("prices/b.db"),
+        ]);
+        assert_eq!(parser.diagnostics.len(), 1);
+        assert_eq!(parser.diagnostics[0].file(), Some(&dir.join("prices/b.db")));
+        assert_eq!(parser.diagnostics[0].line(), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let dir = write_files("cycle", &[
+            ("a.journal", "include b.journal\n2013/01/04 Lunch\n  Expenses:Food  $10\n  Assets:Cash\n"),
+            ("b.journal", "include a.journal\n"),
+        ]);
+        let journal = parse_file(&dir.join("a.journal"), &Options::new()).unwrap();
+
+        assert_eq!(journal.postings().len(), 2);
+        assert_eq!(journal.diagnostics().len(), 1);
+        assert_eq!(journal.diagnostics()[0].file(), Some(&dir.join("b.journal")));
+        assert!(journal.diagnostics()[0].message().starts_with("Include cycle: "));
+
+        let mut options = Options::new();
+        options.set_pedantic(true);
+        let error = parse_file(&dir.join("a.journal"), &options).unwrap_err().to_string();
+        assert!(error.contains("Include cycle: "));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_bad_pattern() {
+        let dir = write_files("bad-pattern", &[("main.journal", "2013/01/04 Lunch\n  Expenses:Food  $10\n  Assets:Cash\n\ninclude foo[z-a].journal\n")]);
+        let mut parser = Parser::new(&Options::new());
+        parser.parse_file(&dir.join("main.journal")).unwrap();
+
+        assert_eq!(parser.journal.postings().len(), 2);
+        assert_eq!(parser.diagnostics.len(), 1);
+        assert_eq!(parser.diagnostics[0].file(), Some(&dir.join("main.journal")));
+        assert_eq!(parser.diagnostics[0].line(), 5);
+        assert_eq!(parser.diagnostics[0].message(), "Could not include foo[z-a].journal: Invalid pattern foo[z-a].journal");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,166 @@
+use chrono::{Datelike, Days, Months, NaiveDate};
+use parser::parse_date;
+use std::fmt;
+
+/// How often a periodic transaction recurs.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interval {
+    Days(u32),
+    Weeks(u32),
+    Months(u32),
+    Years(u32),
+}
+
+/// A period expression such as `Monthly`, `every 2 weeks from 2013/01/07` or
+/// `quarterly from 2013/01/01 to 2014/01/01`. The end date is not part of the period.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Period {
+    text: String,
+    interval: Interval,
+    start: Option<NaiveDate>,
+    end: Option<NaiveDate>,
+}
+
+impl Period {
+    pub fn parse(text: &str) -> Result<Period, String> {
+        let lowercase = text.to_lowercase();
+        let words = lowercase.split_whitespace().collect::<Vec<&str>>();
+        let (interval, mut rest) = interval(&words).ok_or_else(|| format!("Expected a period such as 'monthly', found '{}'", text.trim()))?;
+        let mut period = Period { text: text.trim().to_string(), interval, start: None, end: None };
+        while !rest.is_empty() {
+            let date = rest.get(1).ok_or_else(|| format!("Expected a date after '{}'", rest[0]))?;
+            match rest[0] {
+                "from" | "since" => period.start = Some(parse_date(date)?),
+                "to" | "until" => period.end = Some(parse_date(date)?),
+                word => return Err(format!("Unexpected '{}' in period '{}'", word, text)),
+            }
+            rest = &rest[2..];
+        }
+        Ok(period)
+    }
+    pub fn interval(&self) -> Interval {
+        self.interval
+    }
+    pub fn start(&self) -> Option<NaiveDate> {
+        self.start
+    }
+    pub fn end(&self) -> Option<NaiveDate> {
+        self.end
+    }
+
+    /// The dates the period falls on from `begin` up to but not including `end`. Without a start
+    /// date of its own, a period starts at the beginning of the week, month, quarter or year that
+    /// `begin` falls in; weeks begin on Sunday.
+    pub fn occurrences(&self, begin: NaiveDate, end: NaiveDate) -> Vec<NaiveDate> {
+        let end = self.end.map_or(end, |own_end| own_end.min(end));
+        let start = self.start.unwrap_or_else(|| self.align(begin));
+        let mut dates = Vec::new();
+        for n in 0.. {
+            let date = match self.nth(start, n) {
+                Some(date) if date < end => date,
+                _ => break,
+            };
+            if date >= begin {
+                dates.push(date);
+            }
+        }
+        dates
+    }
+
+    fn align(&self, date: NaiveDate) -> NaiveDate {
+        match self.interval {
+            Interval::Days(_) => date,
+            Interval::Weeks(_) => date - Days::new(u64::from(date.weekday().num_days_from_sunday())),
+            Interval::Months(months) if months % 3 == 0 => {
+                NaiveDate::from_ymd_opt(date.year(), date.month0() / 3 * 3 + 1, 1).unwrap()
+            }
+            Interval::Months(_) => date.with_day(1).unwrap(),
+            Interval::Years(_) => NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap(),
+        }
+    }
+
+    // The date `n` intervals after `start`. Counting from the start rather than from the previous
+    // date keeps `monthly from 2013/01/31` on the last day of short months without drifting.
+    fn nth(&self, start: NaiveDate, n: u32) -> Option<NaiveDate> {
+        match self.interval {
+            Interval::Days(days) => start.checked_add_days(Days::new(u64::from(days) * u64::from(n))),
+            Interval::Weeks(weeks) => start.checked_add_days(Days::new(7 * u64::from(weeks) * u64::from(n))),
+            Interval::Months(months) => start.checked_add_months(Months::new(months.checked_mul(n)?)),
+            Interval::Years(years) => start.checked_add_months(Months::new(years.checked_mul(12)?.checked_mul(n)?)),
+        }
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+// `monthly`, `every month`, or `every 3 months`, and the words after it.
+fn interval<'a, 'b>(words: &'a [&'b str]) -> Option<(Interval, &'a [&'b str])> {
+    let interval = match *words.first()? {
+        "daily" => Interval::Days(1),
+        "weekly" => Interval::Weeks(1),
+        "biweekly" => Interval::Weeks(2),
+        "monthly" => Interval::Months(1),
+        "bimonthly" => Interval::Months(2),
+        "quarterly" => Interval::Months(3),
+        "yearly" | "annually" => Interval::Years(1),
+        "every" => {
+            let (count, unit, rest) = match words.get(1)?.parse::<u32>() {
+                Ok(count) => (count, *words.get(2)?, &words[3..]),
+                Err(_) => (1, *words.get(1)?, &words[2..]),
+            };
+            if count == 0 {
+                return None;
+            }
+            let interval = match unit.trim_end_matches('s') {
+                "day" => Interval::Days(count),
+                "week" => Interval::Weeks(count),
+                "month" => Interval::Months(count),
+                "quarter" => Interval::Months(3 * count),
+                "year" => Interval::Years(count),
+                _ => return None,
+            };
+            return Some((interval, rest));
+        }
+        _ => return None,
+    };
+    Some((interval, &words[1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_parse_period() {
+        assert_eq!(Period::parse("Monthly").unwrap().interval(), Interval::Months(1));
+        assert_eq!(Period::parse("every 2 weeks").unwrap().interval(), Interval::Weeks(2));
+        assert_eq!(Period::parse("Every quarter").unwrap().interval(), Interval::Months(3));
+        let period = Period::parse("yearly from 2013/01/01 until 2015-01-01").unwrap();
+        assert_eq!((period.start(), period.end()), (Some(date(2013, 1, 1)), Some(date(2015, 1, 1))));
+        assert!(Period::parse("fortnightly").is_err());
+        assert!(Period::parse("monthly from").is_err());
+        assert!(Period::parse("every 0 days").is_err());
+    }
+
+    #[test]
+    fn test_occurrences() {
+        let monthly = Period::parse("monthly").unwrap();
+        assert_eq!(monthly.occurrences(date(2013, 1, 15), date(2013, 4, 1)), vec![date(2013, 2, 1), date(2013, 3, 1)]);
+        let weekly = Period::parse("every 2 weeks from 2013/01/07 to 2013/02/01").unwrap();
+        assert_eq!(weekly.occurrences(date(2013, 1, 1), date(2013, 12, 31)), vec![date(2013, 1, 7), date(2013, 1, 21)]);
+        let quarterly = Period::parse("quarterly").unwrap();
+        assert_eq!(quarterly.occurrences(date(2013, 5, 1), date(2014, 1, 1)), vec![date(2013, 7, 1), date(2013, 10, 1)]);
+        let sundays = Period::parse("weekly").unwrap();
+        assert_eq!(sundays.occurrences(date(2013, 1, 1), date(2013, 1, 10)), vec![date(2013, 1, 6)]);
+        let month_ends = Period::parse("monthly from 2013/01/31").unwrap();
+        assert_eq!(month_ends.occurrences(date(2013, 1, 1), date(2013, 5, 1)), vec![date(2013, 1, 31), date(2013, 2, 28), date(2013, 3, 31), date(2013, 4, 30)]);
+    }
+}
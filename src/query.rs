@@ -0,0 +1,157 @@
+use accounting::{Journal, Posting};
+use regex::{Regex, RegexBuilder};
+
+/// A report query, built from the words given on the command line:
+///
+/// * `Assets:Cash` matches account names,
+/// * `payee:Dinner` or `@Dinner` matches payees, after `payee` directive aliases have turned
+///   descriptions such as `WHOLEFDS MKT #123` into the payee they stand for,
+/// * `tag:work` or `%work` matches tags, and `%Receipt=pdf` their values,
+/// * `not`, `and` and `or` combine terms; terms without an operator between them are or'ed.
+///
+/// Patterns are case-insensitive regular expressions.
+#[derive(Debug, Clone)]
+pub enum Query {
+    Account(Regex),
+    Payee(Regex),
+    Tag(Regex, Option<Regex>),
+    Not(Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+}
+
+impl Query {
+    pub fn parse(words: &[&str]) -> Result<Option<Query>, String> {
+        let mut parser = QueryParser { words, current: 0 };
+        if words.is_empty() {
+            return Ok(None);
+        }
+        let query = parser.or()?;
+        match parser.peek() {
+            Some(word) => Err(format!("Unexpected '{}' in query", word)),
+            None => Ok(Some(query)),
+        }
+    }
+
+    pub fn matches(&self, journal: &Journal, posting: &Posting) -> bool {
+        match *self {
+            Query::Account(ref pattern) => pattern.is_match(journal.account(posting.account()).name()),
+            Query::Payee(ref pattern) => pattern.is_match(journal.transaction(posting.transaction()).payee()),
+            Query::Tag(ref name, ref value) => {
+                posting.tags().iter().any(|(tag, tag_value)| {
+                    name.is_match(tag) && match *value {
+                        None => true,
+                        Some(ref value) => tag_value.as_ref().is_some_and(|v| value.is_match(&v.to_string())),
+                    }
+                })
+            }
+            Query::Not(ref query) => !query.matches(journal, posting),
+            Query::And(ref left, ref right) => left.matches(journal, posting) && right.matches(journal, posting),
+            Query::Or(ref left, ref right) => left.matches(journal, posting) || right.matches(journal, posting),
+        }
+    }
+}
+
+struct QueryParser<'a> {
+    words: &'a [&'a str],
+    current: usize,
+}
+
+impl<'a> QueryParser<'a> {
+    fn or(&mut self) -> Result<Query, String> {
+        let mut query = self.and()?;
+        while let Some(word) = self.peek() {
+            if word == "or" {
+                self.current += 1;
+            }
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query, String> {
+        let mut query = self.not()?;
+        while self.peek() == Some("and") {
+            self.current += 1;
+            query = Query::And(Box::new(query), Box::new(self.not()?));
+        }
+        Ok(query)
+    }
+
+    fn not(&mut self) -> Result<Query, String> {
+        if self.peek() == Some("not") {
+            self.current += 1;
+            return Ok(Query::Not(Box::new(self.not()?)));
+        }
+        self.term()
+    }
+
+    fn term(&mut self) -> Result<Query, String> {
+        let word = match self.peek() {
+            Some(word) => word,
+            None => return Err("Expected a query term".to_string()),
+        };
+        self.current += 1;
+        if let Some(term) = word.strip_prefix("tag:").or_else(|| word.strip_prefix('%')) {
+            tag(term)
+        }
+        else if let Some(term) = word.strip_prefix("payee:").or_else(|| word.strip_prefix('@')) {
+            Ok(Query::Payee(pattern(term)?))
+        }
+        else {
+            Ok(Query::Account(pattern(word)?))
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.words.get(self.current).cloned()
+    }
+}
+
+fn tag(term: &str) -> Result<Query, String> {
+    match term.find('=') {
+        Some(index) => Ok(Query::Tag(pattern(&term[..index])?, Some(pattern(&term[index + 1..])?))),
+        None => Ok(Query::Tag(pattern(term)?, None)),
+    }
+}
+
+fn pattern(pattern: &str) -> Result<Regex, String> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|err| format!("Invalid pattern '{}': {}", pattern, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::lex;
+    use parser::parse;
+
+    fn matching(query: &[&str], journal: &str) -> Vec<String> {
+        let query = Query::parse(query).unwrap().unwrap();
+        let journal = parse(lex(journal));
+        journal.postings().iter()
+            .filter(|posting| query.matches(&journal, posting))
+            .map(|posting| journal.account(posting.account()).name().clone())
+            .collect()
+    }
+
+    const JOURNAL: &str = "2013/01/04 Lunch  ; :reimbursable:\n  Expenses:Food  $10  ; Receipt: lunch.pdf\n  Assets:Cash\n\n2013/01/05 Groceries\n  Expenses:Food  $20\n  Assets:Cash\n";
+
+    #[test]
+    fn test_tag_queries() {
+        assert_eq!(matching(&["tag:reimbursable"], JOURNAL), vec!["Expenses:Food", "Assets:Cash"]);
+        assert_eq!(matching(&["%Receipt"], JOURNAL), vec!["Expenses:Food"]);
+        assert_eq!(matching(&["%receipt=pdf$"], JOURNAL), vec!["Expenses:Food"]);
+        assert!(matching(&["%Receipt=png"], JOURNAL).is_empty());
+    }
+
+    #[test]
+    fn test_combined_queries() {
+        assert_eq!(matching(&["food", "and", "not", "tag:reimbursable"], JOURNAL), vec!["Expenses:Food"]);
+        assert_eq!(matching(&["@groceries", "and", "cash"], JOURNAL), vec!["Assets:Cash"]);
+        assert_eq!(matching(&["food", "cash"], JOURNAL).len(), 4);
+        assert!(Query::parse(&["not"]).is_err());
+    }
+}
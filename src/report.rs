@@ -0,0 +1,321 @@
+use accounting::{Amount, Balance, CommoditySymbol, Journal, Posting, PostingKind, Transaction};
+use chrono::NaiveDate;
+use query::Query;
+use status::Status;
+use tree::{AccountTree, NodeId};
+use std::io::{Result, Write};
+
+/// Shows amounts at their market value rather than in the commodities they were posted in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Valuation {
+    commodity: Option<CommoditySymbol>,
+    date: Option<NaiveDate>,
+}
+
+impl Valuation {
+    /// Values amounts in `commodity` (`-X`), or with `None` in whatever each commodity is priced
+    /// in (`-V`). Prices are those on `date`, or with `None` on the date of each posting.
+    pub fn new(commodity: Option<CommoditySymbol>, date: Option<NaiveDate>) -> Valuation {
+        Valuation { commodity, date }
+    }
+    pub fn commodity(&self) -> Option<&CommoditySymbol> {
+        self.commodity.as_ref()
+    }
+    pub fn date(&self) -> Option<NaiveDate> {
+        self.date
+    }
+
+    fn value(&self, journal: &Journal, posting: &Posting, amount: &Amount) -> Amount {
+        let date = self.date.unwrap_or_else(|| journal.transaction(posting.transaction()).date());
+        journal.market_value(amount, self.commodity.as_deref(), date)
+    }
+}
+
+/// Each account with postings matching the query and its total, with the accounts below it
+/// indented beneath it, followed by the grand total.
+pub fn balance<W: Write>(out: &mut W, journal: &Journal, query: Option<&Query>, valuation: Option<&Valuation>) -> Result<()> {
+    // Totals are kept in the smallest unit each commodity converts into, so hours and minutes
+    // add up.
+    let mut tree = AccountTree::new();
+    for posting in matching(journal, query) {
+        let name = journal.account(posting.account()).name();
+        tree.insert(name);
+        if let Some(amount) = amount(journal, posting, valuation) {
+            tree.post(name, journal.reduce(&amount));
+        }
+    }
+
+    for &id in tree.children(tree.root()) {
+        if shown(journal, &tree, id) {
+            write_account(out, journal, &tree, id, tree.name(id).to_string(), 0)?;
+        }
+    }
+    writeln!(out, "{}", "-".repeat(20))?;
+    write_stacked(out, &journal.format_balance(tree.total(tree.root())), "")
+}
+
+// Whether an account or any account below it has a total that isn't zero.
+fn shown(journal: &Journal, tree: &AccountTree, id: NodeId) -> bool {
+    journal.format_balance(tree.total(id)) != ["0"] || tree.children(id).iter().any(|&child| shown(journal, tree, child))
+}
+
+// Like ledger, an account with nothing posted to it directly and only one account to show below
+// it is written on the same line as that account, as in `Equity:Opening Balance`.
+fn write_account<W: Write>(out: &mut W, journal: &Journal, tree: &AccountTree, id: NodeId, name: String, depth: usize) -> Result<()> {
+    let children = tree.children(id).iter().cloned().filter(|&child| shown(journal, tree, child)).collect::<Vec<NodeId>>();
+    if children.len() == 1 && journal.format_balance(tree.balance(id)) == ["0"] {
+        let child = children[0];
+        return write_account(out, journal, tree, child, format!("{}:{}", name, tree.name(child)), depth);
+    }
+    write_stacked(out, &journal.format_balance(tree.total(id)), &format!("  {}{}", "  ".repeat(depth), name))?;
+    for child in children {
+        write_account(out, journal, tree, child, tree.name(child).to_string(), depth + 1)?;
+    }
+    Ok(())
+}
+
+/// Each posting matching the query with the running total after it.
+pub fn register<W: Write>(out: &mut W, journal: &Journal, query: Option<&Query>, valuation: Option<&Valuation>) -> Result<()> {
+    let mut running_total = Balance::new();
+    for posting in matching(journal, query) {
+        let amount = amount(journal, posting, valuation);
+        if let Some(ref amount) = amount {
+            running_total += journal.reduce(amount);
+        }
+        let amount = amount.map(|amount| journal.format_amount(&amount)).unwrap_or_default();
+        let totals = journal.format_balance(&running_total);
+        let transaction = journal.transaction(posting.transaction());
+        writeln!(out, "{} {} {} {:>12} {:>12}",
+                 transaction.date().format("%Y-%m-%d"),
+                 column(transaction.payee(), 22),
+                 column(journal.account(posting.account()).name(), 22),
+                 amount,
+                 totals[0])?;
+        for total in &totals[1..] {
+            writeln!(out, "{:>83}", total)?;
+        }
+    }
+    Ok(())
+}
+
+/// Each transaction with a posting matching the query, written back out as a journal entry.
+/// Postings added by automated transactions are only shown if `generated` is set.
+pub fn print<W: Write>(out: &mut W, journal: &Journal, query: Option<&Query>, generated: bool) -> Result<()> {
+    let mut first = true;
+    for transaction in journal.transactions() {
+        let entry = transaction.postings().iter()
+            .map(|&id| journal.posting(id))
+            .filter(|posting| generated || !posting.is_generated())
+            .collect::<Vec<&Posting>>();
+        if entry.is_empty() || query.is_some_and(|query| !entry.iter().any(|posting| query.matches(journal, posting))) {
+            continue;
+        }
+        if !first {
+            writeln!(out)?;
+        }
+        first = false;
+        write_transaction(out, journal, transaction, &entry)?;
+    }
+    Ok(())
+}
+
+fn write_transaction<W: Write>(out: &mut W, journal: &Journal, transaction: &Transaction, postings: &[&Posting]) -> Result<()> {
+    let mut header = transaction.date().format("%Y/%m/%d").to_string();
+    if let Some(edate) = transaction.edate() {
+        header.push_str(&edate.format("=%Y/%m/%d").to_string());
+    }
+    match transaction.status() {
+        Status::Cleared => header.push_str(" *"),
+        Status::Pending => header.push_str(" !"),
+        Status::Unmarked => {}
+    }
+    if let Some(code) = transaction.code() {
+        header.push_str(&format!(" ({})", code));
+    }
+    header.push(' ');
+    header.push_str(transaction.description());
+    write_with_note(out, &header, transaction.note())?;
+
+    for posting in postings {
+        let name = journal.account(posting.account()).name();
+        let account = match posting.kind() {
+            PostingKind::Real => name.clone(),
+            PostingKind::Virtual => format!("({})", name),
+            PostingKind::BalancedVirtual => format!("[{}]", name),
+        };
+        let mut line = format!("    {}", account);
+        if let Some(amount) = posting.amount() {
+            let amount = journal.format_amount(amount);
+            let width = 48usize.saturating_sub(line.chars().count() + 2).max(amount.chars().count());
+            line.push_str(&format!("  {:>width$}", amount, width = width));
+        }
+        if let Some(cost) = posting.cost() {
+            line.push_str(&format!(" @ {}", journal.format_amount(cost)));
+        }
+        write_with_note(out, &line, posting.note())?;
+    }
+    Ok(())
+}
+
+// The first line of a note follows the text it belongs to; any others go on lines of their own.
+fn write_with_note<W: Write>(out: &mut W, text: &str, note: Option<&String>) -> Result<()> {
+    let mut lines = note.map(|note| note.lines().collect::<Vec<&str>>()).unwrap_or_default().into_iter();
+    match lines.next() {
+        Some(first) => writeln!(out, "{}  ; {}", text, first)?,
+        None => writeln!(out, "{}", text)?,
+    }
+    for line in lines {
+        writeln!(out, "    ; {}", line)?;
+    }
+    Ok(())
+}
+
+fn matching<'a>(journal: &'a Journal, query: Option<&'a Query>) -> impl Iterator<Item = &'a Posting> {
+    journal.postings().iter().filter(move |posting| query.is_none_or(|query| query.matches(journal, posting)))
+}
+
+fn amount(journal: &Journal, posting: &Posting, valuation: Option<&Valuation>) -> Option<Amount> {
+    let amount = posting.amount()?;
+    Some(match valuation {
+        Some(valuation) => valuation.value(journal, posting, amount),
+        None => amount.clone(),
+    })
+}
+
+// Amounts right-aligned one above the other, with the label on the last line.
+fn write_stacked<W: Write>(out: &mut W, lines: &[String], label: &str) -> Result<()> {
+    for (i, line) in lines.iter().enumerate() {
+        if i + 1 == lines.len() {
+            writeln!(out, "{:>20}{}", line, label)?;
+        }
+        else {
+            writeln!(out, "{:>20}", line)?;
+        }
+    }
+    Ok(())
+}
+
+fn column(text: &str, width: usize) -> String {
+    let truncated = if text.chars().count() > width {
+        format!("{}..", text.chars().take(width - 2).collect::<String>())
+    }
+    else {
+        text.to_string()
+    };
+    format!("{:<width$}", truncated, width = width)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::lex;
+    use parser::parse;
+
+    fn balance_report(journal: &str, query: &[&str]) -> String {
+        let journal = parse(lex(journal));
+        let query = Query::parse(query).unwrap();
+        let mut out = Vec::new();
+        balance(&mut out, &journal, query.as_ref(), None).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_balance_uses_commodity_format() {
+        let report = balance_report("commodity $\n    format $1,000.00\ncommodity EUR\n    format 1.000,0 EUR\n\n2013/01/01 Opening\n  Assets:Checking  $1234.5\n  Assets:Euro  1000 EUR\n  Equity:Opening Balance\n", &[]);
+        assert_eq!(report, concat!(
+            "           $1,234.50\n",
+            "         1.000,0 EUR  Assets\n",
+            "           $1,234.50    Checking\n",
+            "         1.000,0 EUR    Euro\n",
+            "          $-1,234.50\n",
+            "        -1.000,0 EUR  Equity:Opening Balance\n",
+            "--------------------\n",
+            "                   0\n"));
+    }
+
+    #[test]
+    fn test_balance_is_hierarchical() {
+        let report = balance_report("2013/01/01 Opening\n  Assets:Bank of America  -600\n  Assets:Cash  20\n  Assets:Savings  600\n  Equity:Opening Balance\n\n2013/01/02 Spending\n  Expenses:Food  160\n  Expenses:Rent  1560\n  Equity:Opening Balance\n", &[]);
+        assert_eq!(report, concat!(
+            "                  20  Assets\n",
+            "                -600    Bank of America\n",
+            "                  20    Cash\n",
+            "                 600    Savings\n",
+            "               -1740  Equity:Opening Balance\n",
+            "                1720  Expenses\n",
+            "                 160    Food\n",
+            "                1560    Rent\n",
+            "--------------------\n",
+            "                   0\n"));
+    }
+
+    #[test]
+    fn test_balance_with_query() {
+        let report = balance_report("2013/01/04 Dinner\n  Expenses:Food  ($120 / 3)\n  Assets:Cash\n", &["food"]);
+        assert_eq!(report, "              $40.00  Expenses:Food\n--------------------\n              $40.00\n");
+    }
+
+    #[test]
+    fn test_register() {
+        let journal = parse(lex("2013/01/04 Dinner with some very good friends\n  Expenses:Food  $40\n  Assets:Cash\n"));
+        let mut out = Vec::new();
+        register(&mut out, &journal, None, None).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), concat!(
+            "2013-01-04 Dinner with some ver.. Expenses:Food                   $40          $40\n",
+            "2013-01-04 Dinner with some ver.. Assets:Cash                    $-40            0\n"));
+    }
+
+    const PORTFOLIO: &str = "P 2013-01-31 AAPL $450\nP 2013-02-28 AAPL $500\n\n2013/01/31 Buy\n  Assets:Brokerage  2 AAPL @ $450\n  Assets:Cash\n\n2013/02/28 Buy\n  Assets:Brokerage  1 AAPL @ $500\n  Assets:Cash\n";
+
+    fn valued_register(valuation: &Valuation) -> String {
+        let journal = parse(lex(PORTFOLIO));
+        let query = Query::parse(&["brokerage"]).unwrap();
+        let mut out = Vec::new();
+        register(&mut out, &journal, query.as_ref(), Some(valuation)).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_market_valuation() {
+        let journal = parse(lex(PORTFOLIO));
+        let mut out = Vec::new();
+        let valuation = Valuation::new(Some("$".to_string()), NaiveDate::from_ymd_opt(2013, 3, 1));
+        balance(&mut out, &journal, Some(&Query::parse(&["brokerage"]).unwrap().unwrap()), Some(&valuation)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "               $1500  Assets:Brokerage\n--------------------\n               $1500\n");
+
+        assert_eq!(valued_register(&Valuation::new(None, NaiveDate::from_ymd_opt(2013, 2, 1))), concat!(
+            "2013-01-31 Buy                    Assets:Brokerage               $900         $900\n",
+            "2013-02-28 Buy                    Assets:Brokerage               $450        $1350\n"));
+        assert_eq!(valued_register(&Valuation::new(None, None)), concat!(
+            "2013-01-31 Buy                    Assets:Brokerage               $900         $900\n",
+            "2013-02-28 Buy                    Assets:Brokerage               $500        $1400\n"));
+    }
+
+    #[test]
+    fn test_print_generated_postings() {
+        let journal = parse(lex("= /^Income:Salary/\n  (Assets:Savings)  0.1\n\n2013/01/31 * Payday  ; January\n  Assets:Checking  $2,000.00\n  Income:Salary\n"));
+        let print_report = |generated| {
+            let mut out = Vec::new();
+            print(&mut out, &journal, None, generated).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(print_report(false), concat!(
+            "2013/01/31 * Payday  ; January\n",
+            "    Assets:Checking                    $2,000.00\n",
+            "    Income:Salary                     $-2,000.00\n"));
+        assert_eq!(print_report(true), concat!(
+            "2013/01/31 * Payday  ; January\n",
+            "    Assets:Checking                    $2,000.00\n",
+            "    Income:Salary                     $-2,000.00\n",
+            "    (Assets:Savings)                    $-200.00\n"));
+    }
+
+    #[test]
+    fn test_print_plain_numbers() {
+        let text = "2013/01/04 Count\n    Assets:A                                  10\n    Assets:B                                 -10\n";
+        let journal = parse(lex(text));
+        let mut out = Vec::new();
+        print(&mut out, &journal, None, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), text);
+    }
+}
@@ -0,0 +1,260 @@
+use accounting::{Amount, Balance, Journal};
+use std::collections::HashMap;
+
+/// An account in an `AccountTree`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Debug, Clone)]
+struct Node {
+    segment: usize,
+    parent: Option<NodeId>,
+    // Ordered by name.
+    children: Vec<NodeId>,
+    depth: usize,
+    balance: Balance,
+    total: Balance,
+}
+
+/// Accounts arranged by the colon-separated segments of their names, so that `Assets:Cash` is a
+/// child of `Assets`. Each segment name is stored once however many accounts share it. Every
+/// account has the balance of its own postings, and a total that includes the accounts below it.
+///
+/// The root has no name and stands for the whole journal; its total is the grand total.
+#[derive(Debug, Clone)]
+pub struct AccountTree {
+    segments: Vec<String>,
+    segment_ids: HashMap<String, usize>,
+    nodes: Vec<Node>,
+}
+
+impl Default for AccountTree {
+    fn default() -> AccountTree {
+        let root = Node { segment: 0, parent: None, children: Vec::new(), depth: 0, balance: Balance::new(), total: Balance::new() };
+        AccountTree {
+            segments: vec![String::new()],
+            segment_ids: HashMap::new(),
+            nodes: vec![root],
+        }
+    }
+}
+
+impl AccountTree {
+    pub fn new() -> AccountTree {
+        AccountTree::default()
+    }
+
+    /// Every account of the journal with the amounts posted to it, in the smallest unit each
+    /// commodity converts into.
+    pub fn from_journal(journal: &Journal) -> AccountTree {
+        let mut tree = AccountTree::new();
+        for account in journal.accounts() {
+            tree.insert(account.name());
+        }
+        for posting in journal.postings() {
+            if let Some(amount) = posting.amount() {
+                tree.post(journal.account(posting.account()).name(), journal.reduce(amount));
+            }
+        }
+        tree
+    }
+
+    /// The account with this name, adding it and any accounts above it that are missing.
+    pub fn insert(&mut self, name: &str) -> NodeId {
+        let mut node = self.root();
+        for segment in name.split(':') {
+            node = match self.child(node, segment) {
+                Ok(index) => self.nodes[node.0].children[index],
+                Err(index) => {
+                    let id = NodeId(self.nodes.len());
+                    let segment = self.intern(segment);
+                    let depth = self.nodes[node.0].depth + 1;
+                    self.nodes.push(Node { segment, parent: Some(node), children: Vec::new(), depth, balance: Balance::new(), total: Balance::new() });
+                    self.nodes[node.0].children.insert(index, id);
+                    id
+                }
+            };
+        }
+        node
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        let mut node = self.root();
+        for segment in name.split(':') {
+            let index = self.child(node, segment).ok()?;
+            node = self.nodes[node.0].children[index];
+        }
+        Some(node)
+    }
+
+    /// Adds an amount to the balance of an account, and so to the totals of it and every
+    /// account above it.
+    pub fn post(&mut self, name: &str, amount: Amount) {
+        let id = self.insert(name);
+        self.nodes[id.0].balance += amount.clone();
+        let mut node = Some(id);
+        while let Some(id) = node {
+            self.nodes[id.0].total += amount.clone();
+            node = self.nodes[id.0].parent;
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+    /// The number of accounts, not counting the root.
+    pub fn len(&self) -> usize {
+        self.nodes.len() - 1
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The last segment of the account's name, such as `Cash` for `Assets:Cash`.
+    pub fn name(&self, id: NodeId) -> &str {
+        &self.segments[self.nodes[id.0].segment]
+    }
+    pub fn full_name(&self, id: NodeId) -> String {
+        let mut names = self.ancestors(id).map(|ancestor| self.name(ancestor)).collect::<Vec<&str>>();
+        names.reverse();
+        names.push(self.name(id));
+        names.join(":")
+    }
+    /// `None` only for the root. A top-level account's parent is the root.
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].parent
+    }
+    /// The accounts directly below, ordered by name.
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.nodes[id.0].children
+    }
+    /// How many segments the account's name has. The root is at depth 0.
+    pub fn depth(&self, id: NodeId) -> usize {
+        self.nodes[id.0].depth
+    }
+    /// The depth of the deepest account.
+    pub fn max_depth(&self) -> usize {
+        self.nodes.iter().map(|node| node.depth).max().unwrap_or(0)
+    }
+    /// The balance of the account's own postings.
+    pub fn balance(&self, id: NodeId) -> &Balance {
+        &self.nodes[id.0].balance
+    }
+    /// The balance of the account and every account below it.
+    pub fn total(&self, id: NodeId) -> &Balance {
+        &self.nodes[id.0].total
+    }
+
+    /// The accounts above this one, nearest first, not counting the root.
+    pub fn ancestors(&self, id: NodeId) -> Ancestors<'_> {
+        Ancestors { tree: self, next: self.parent(id).filter(|parent| *parent != self.root()) }
+    }
+    /// The accounts below this one, each followed by those below it in name order.
+    pub fn descendants(&self, id: NodeId) -> Descendants<'_> {
+        Descendants { tree: self, stack: self.children(id).iter().rev().cloned().collect() }
+    }
+    /// Every account, each followed by those below it in name order.
+    pub fn iter(&self) -> Descendants<'_> {
+        self.descendants(self.root())
+    }
+    /// The accounts with this many segments in their names, in name order.
+    pub fn at_depth(&self, depth: usize) -> impl Iterator<Item = NodeId> + '_ {
+        self.iter().filter(move |id| self.depth(*id) == depth)
+    }
+
+    fn intern(&mut self, segment: &str) -> usize {
+        if let Some(&index) = self.segment_ids.get(segment) {
+            return index;
+        }
+        self.segments.push(segment.to_string());
+        self.segment_ids.insert(segment.to_string(), self.segments.len() - 1);
+        self.segments.len() - 1
+    }
+
+    // Where the child with this name is among the children of `node`, or where it would go.
+    fn child(&self, node: NodeId, segment: &str) -> Result<usize, usize> {
+        self.nodes[node.0].children.binary_search_by(|child| self.name(*child).cmp(segment))
+    }
+}
+
+pub struct Ancestors<'a> {
+    tree: &'a AccountTree,
+    next: Option<NodeId>,
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.tree.parent(id).filter(|parent| *parent != self.tree.root());
+        Some(id)
+    }
+}
+
+pub struct Descendants<'a> {
+    tree: &'a AccountTree,
+    stack: Vec<NodeId>,
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.stack.pop()?;
+        self.stack.extend(self.tree.children(id).iter().rev());
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use accounting::CommodityId;
+    use lexer::lex;
+    use parser::parse;
+
+    fn dollars(number: &str) -> Balance {
+        Balance::from(Amount::parse(CommodityId::intern("$"), number).unwrap())
+    }
+
+    #[test]
+    fn test_tree() {
+        let journal = parse(lex("2013/01/01 Opening\n  Assets:Bank of America  $-600\n  Assets:Cash  $20\n  Assets:Savings  $600\n  Equity:Opening Balance\n\n2013/01/02 Spending\n  Expenses:Food  $160\n  Expenses:Rent  $1560\n  Assets  $-1720\n"));
+        let tree = AccountTree::from_journal(&journal);
+        let names = tree.iter().map(|id| tree.full_name(id)).collect::<Vec<String>>();
+        assert_eq!(names, vec!["Assets", "Assets:Bank of America", "Assets:Cash", "Assets:Savings", "Equity", "Equity:Opening Balance", "Expenses", "Expenses:Food", "Expenses:Rent"]);
+        assert_eq!(tree.len(), 9);
+
+        let assets = tree.find("Assets").unwrap();
+        let cash = tree.find("Assets:Cash").unwrap();
+        assert_eq!(tree.name(cash), "Cash");
+        assert_eq!(tree.parent(cash), Some(assets));
+        assert_eq!(tree.parent(assets), Some(tree.root()));
+        assert_eq!(tree.children(assets).iter().map(|id| tree.name(*id)).collect::<Vec<&str>>(), vec!["Bank of America", "Cash", "Savings"]);
+        assert_eq!(tree.ancestors(cash).collect::<Vec<NodeId>>(), vec![assets]);
+        assert_eq!(tree.descendants(assets).count(), 3);
+        assert!(tree.find("Assets:Checking").is_none());
+
+        assert_eq!(tree.depth(tree.root()), 0);
+        assert_eq!(tree.depth(cash), 2);
+        assert_eq!(tree.max_depth(), 2);
+        assert_eq!(tree.at_depth(1).map(|id| tree.name(id)).collect::<Vec<&str>>(), vec!["Assets", "Equity", "Expenses"]);
+
+        assert_eq!(tree.balance(assets), &dollars("-1720"));
+        assert_eq!(tree.total(assets), &dollars("-1700"));
+        assert_eq!(tree.total(tree.find("Expenses").unwrap()), &dollars("1720"));
+        assert!(tree.balance(tree.find("Expenses").unwrap()).is_empty());
+        assert!(tree.total(tree.root()).is_empty());
+    }
+
+    #[test]
+    fn test_segments_are_interned() {
+        let mut tree = AccountTree::new();
+        let food = tree.insert("Expenses:Food");
+        assert_eq!(tree.insert("Expenses:Food"), food);
+        tree.insert("Budget:Expenses:Food");
+        assert_eq!(tree.len(), 5);
+        assert_eq!(tree.segments.len(), 4);
+    }
+}
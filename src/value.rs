@@ -0,0 +1,576 @@
+use accounting::{format_quantity, parse_quantity, AccountName, Amount, Balance, CommodityId, Journal, Posting, Quantity};
+use chrono::NaiveDate;
+use num::{pow, BigInt, Signed, Zero};
+use parser::parse_date;
+use regex::Regex;
+use std::cmp::Ordering;
+use std::fmt;
+
+// Value expressions, as written after `assert` and `check`:
+//
+//     expression → or
+//     or         → and ( ( "or" | "||" ) and )*
+//     and        → not ( ( "and" | "&&" ) not )*
+//     not        → ( "not" | "!" ) not | comparison
+//     comparison → term ( ( "==" | "!=" | "<" | "<=" | ">" | ">=" | "=~" ) term )?
+//     term       → factor ( ( "+" | "-" ) factor )*
+//     factor     → unary ( ( "*" | "/" ) unary )*
+//     unary      → "-" unary | call
+//     call       → primary ( "." identifier )*
+//     primary    → number | commodity number | string | /regex/ | [date] | "(" expression ")"
+//                | identifier ( "(" ( expression ( "," expression )* )? ")" )?
+//
+// Within an account block the expression is evaluated for each posting to the account, and can
+// use `amount`, `commodity`, `account`, `payee`, `date` and `note`; within a tag block `value`
+// is also the value of the tag. `account("Assets:Cash")` names any account, whose `total` and
+// `name` can be asked for.
+#[derive(Debug, Clone)]
+pub enum ValueExpr {
+    Binary { left: Box<ValueExpr>, operator: String, right: Box<ValueExpr> },
+    Call { function: String, arguments: Vec<ValueExpr> },
+    Field { value: Box<ValueExpr>, field: String },
+    Literal(Value),
+    Unary { operator: String, right: Box<ValueExpr> },
+    Variable(String),
+}
+
+/// The result of a value expression.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Bool(bool),
+    Number(Quantity),
+    Amount(Amount),
+    // The total of several postings in more than one commodity.
+    Balance(Balance),
+    String(String),
+    Regex(Regex),
+    Date(NaiveDate),
+    Account(AccountName),
+}
+
+impl Value {
+    pub fn is_true(&self) -> bool {
+        match *self {
+            Value::Bool(b) => b,
+            Value::Number(ref n) => !n.is_zero(),
+            Value::Amount(ref amount) => !amount.is_zero(),
+            Value::Balance(ref balance) => !balance.is_empty(),
+            Value::String(ref s) => !s.is_empty(),
+            Value::Regex(_) | Value::Date(_) | Value::Account(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(ref n) => write!(f, "{}", number_string(n)),
+            Value::Amount(ref amount) => write!(f, "{}", amount_string(amount)),
+            Value::Balance(ref balance) => {
+                let amounts = balance.amounts().map(amount_string).collect::<Vec<String>>();
+                write!(f, "{}", amounts.join(", "))
+            }
+            Value::String(ref s) => write!(f, "\"{}\"", s),
+            Value::Regex(ref regex) => write!(f, "/{}/", regex),
+            Value::Date(date) => write!(f, "[{}]", date.format("%Y/%m/%d")),
+            Value::Account(ref name) => write!(f, "account(\"{}\")", name),
+        }
+    }
+}
+
+impl ValueExpr {
+    pub fn parse(text: &str) -> Result<ValueExpr, String> {
+        let tokens = scan(text)?;
+        let mut parser = ValueParser { tokens, current: 0 };
+        let expr = parser.or()?;
+        match parser.peek() {
+            Some(token) => Err(format!("Unexpected '{}' in expression", token)),
+            None => Ok(expr),
+        }
+    }
+
+    /// Evaluates the expression against the journal, and the posting being checked if any.
+    pub fn evaluate(&self, journal: &Journal, posting: Option<&Posting>) -> Result<Value, String> {
+        self.eval(&Context { journal, posting, value: None })
+    }
+
+    /// Evaluates the expression for a tag on the posting, whose value is `value`.
+    pub fn evaluate_tag(&self, journal: &Journal, posting: &Posting, value: &Value) -> Result<Value, String> {
+        self.eval(&Context { journal, posting: Some(posting), value: Some(value) })
+    }
+
+    fn eval(&self, context: &Context) -> Result<Value, String> {
+        match *self {
+            ValueExpr::Literal(ref value) => Ok(value.clone()),
+            ValueExpr::Variable(ref name) => variable(name, context),
+            ValueExpr::Field { ref value, ref field } => match value.eval(context)? {
+                Value::Account(ref name) => match field.as_str() {
+                    "total" => Ok(total(context.journal, name)),
+                    "name" => Ok(Value::String(name.clone())),
+                    _ => Err(format!("Accounts have no '{}'", field)),
+                },
+                other => Err(format!("{} has no '{}'", other, field)),
+            },
+            ValueExpr::Call { ref function, ref arguments } => {
+                let arguments = arguments.iter()
+                    .map(|argument| argument.eval(context))
+                    .collect::<Result<Vec<Value>, String>>()?;
+                call(function, &arguments)
+            }
+            ValueExpr::Unary { ref operator, ref right } => {
+                let right = right.eval(context)?;
+                match operator.as_str() {
+                    "-" => negate(&right),
+                    _ => Ok(Value::Bool(!right.is_true())),
+                }
+            }
+            ValueExpr::Binary { ref left, ref operator, ref right } => {
+                let left = left.eval(context)?;
+                // `and` and `or` only look at the right hand side if they need to.
+                match operator.as_str() {
+                    "and" => return Ok(Value::Bool(left.is_true() && right.eval(context)?.is_true())),
+                    "or" => return Ok(Value::Bool(left.is_true() || right.eval(context)?.is_true())),
+                    _ => {}
+                }
+                let right = right.eval(context)?;
+                binary(&left, operator, &right)
+            }
+        }
+    }
+}
+
+// What the variables of an expression refer to.
+struct Context<'a> {
+    journal: &'a Journal,
+    posting: Option<&'a Posting>,
+    value: Option<&'a Value>,
+}
+
+fn variable(name: &str, context: &Context) -> Result<Value, String> {
+    if name == "value" {
+        return context.value.cloned().ok_or_else(|| "'value' is only known within a tag block".to_string());
+    }
+    let posting = context.posting.ok_or_else(|| format!("'{}' is only known within an account block", name))?;
+    match name {
+        "amount" => Ok(posting.amount().map_or(Value::Number(Quantity::zero()), |amount| Value::Amount(amount.clone()))),
+        "commodity" => Ok(Value::String(posting.amount().map(|amount| amount.symbol().to_string()).unwrap_or_default())),
+        "account" => Ok(Value::String(context.journal.account(posting.account()).name().clone())),
+        "payee" => Ok(Value::String(context.journal.transaction(posting.transaction()).payee().clone())),
+        "date" => Ok(Value::Date(context.journal.transaction(posting.transaction()).date())),
+        "note" => Ok(Value::String(posting.note().cloned().unwrap_or_default())),
+        _ => Err(format!("Unknown variable '{}'", name)),
+    }
+}
+
+// The total of an account and the accounts below it.
+fn total(journal: &Journal, name: &str) -> Value {
+    let mut total = Balance::new();
+    for posting in journal.postings() {
+        let account = journal.account(posting.account()).name();
+        let below = account.len() > name.len() && account.starts_with(name) && account[name.len()..].starts_with(':');
+        if account == name || below {
+            if let Some(amount) = posting.amount() {
+                total += journal.reduce(amount);
+            }
+        }
+    }
+    let total = total.amounts().fold(Balance::new(), |balance, amount| balance + journal.unreduce(amount));
+    match total.len() {
+        0 => Value::Number(Quantity::zero()),
+        1 => Value::Amount(total.amounts().next().unwrap().clone()),
+        _ => Value::Balance(total),
+    }
+}
+
+fn call(function: &str, arguments: &[Value]) -> Result<Value, String> {
+    match (function, arguments) {
+        ("account", [Value::String(name)]) => Ok(Value::Account(name.clone())),
+        ("abs", [Value::Number(n)]) => Ok(Value::Number(n.abs())),
+        ("abs", [Value::Amount(amount)]) => Ok(Value::Amount(amount.abs())),
+        ("quantity", [Value::Amount(amount)]) => Ok(Value::Number(amount.quantity().clone())),
+        ("quantity", [Value::Number(n)]) => Ok(Value::Number(n.clone())),
+        ("commodity", [Value::Amount(amount)]) => Ok(Value::String(amount.symbol().to_string())),
+        ("commodity", [Value::Number(_)]) => Ok(Value::String(String::new())),
+        _ => {
+            let arguments = arguments.iter().map(|argument| argument.to_string()).collect::<Vec<String>>();
+            Err(format!("Cannot call {}({})", function, arguments.join(", ")))
+        }
+    }
+}
+
+fn negate(value: &Value) -> Result<Value, String> {
+    match *value {
+        Value::Number(ref n) => Ok(Value::Number(-n.clone())),
+        Value::Amount(ref amount) => Ok(Value::Amount(-amount.clone())),
+        Value::Balance(ref balance) => Ok(Value::Balance(-balance.clone())),
+        ref other => Err(format!("Cannot negate {}", other)),
+    }
+}
+
+fn binary(left: &Value, operator: &str, right: &Value) -> Result<Value, String> {
+    if operator == "=~" {
+        return match (left, right) {
+            (Value::String(s), Value::Regex(regex)) => Ok(Value::Bool(regex.is_match(s))),
+            _ => Err(format!("Cannot match {} against {}", left, right)),
+        };
+    }
+    if let Value::Balance(ref balance) = *left {
+        // A comparison holds for a balance if it holds for every commodity in it.
+        if is_comparison(operator) {
+            for amount in balance.amounts() {
+                if !binary(&Value::Amount(amount.clone()), operator, right)?.is_true() {
+                    return Ok(Value::Bool(false));
+                }
+            }
+            return Ok(Value::Bool(true));
+        }
+    }
+    if is_comparison(operator) {
+        let ordering = compare(left, right)?;
+        let result = match operator {
+            "==" => ordering == Ordering::Equal,
+            "!=" => ordering != Ordering::Equal,
+            "<" => ordering == Ordering::Less,
+            "<=" => ordering != Ordering::Greater,
+            ">" => ordering == Ordering::Greater,
+            _ => ordering != Ordering::Less,
+        };
+        return Ok(Value::Bool(result));
+    }
+    let (left, right) = amounts(left, right)?;
+    let result = match operator {
+        "+" => left.checked_add(right)?,
+        "-" => left.checked_sub(right)?,
+        "*" => left.checked_mul(right)?,
+        _ => left.checked_div(right)?,
+    };
+    Ok(if result.commodity() == CommodityId::NONE { Value::Number(result.quantity().clone()) } else { Value::Amount(result) })
+}
+
+fn is_comparison(operator: &str) -> bool {
+    matches!(operator, "==" | "!=" | "<" | "<=" | ">" | ">=")
+}
+
+fn compare(left: &Value, right: &Value) -> Result<Ordering, String> {
+    match (left, right) {
+        (Value::String(l), Value::String(r)) => Ok(l.cmp(r)),
+        (Value::Date(l), Value::Date(r)) => Ok(l.cmp(r)),
+        (Value::Bool(l), Value::Bool(r)) => Ok(l.cmp(r)),
+        (Value::Account(l), Value::Account(r)) => Ok(l.cmp(r)),
+        _ => {
+            let (l, r) = amounts(left, right)?;
+            Ok(l.quantity().cmp(r.quantity()))
+        }
+    }
+}
+
+// Two numbers or amounts as amounts, a number being one without a commodity. A plain number goes
+// with an amount in any commodity, but two amounts must share theirs.
+fn amounts(left: &Value, right: &Value) -> Result<(Amount, Amount), String> {
+    let amount = |value: &Value| match *value {
+        Value::Number(ref n) => Some(Amount::new(CommodityId::NONE, n.clone())),
+        Value::Amount(ref amount) => Some(amount.clone()),
+        _ => None,
+    };
+    match (amount(left), amount(right)) {
+        (Some(l), Some(r)) if l.commodity() == CommodityId::NONE || r.commodity() == CommodityId::NONE || l.commodity() == r.commodity() => Ok((l, r)),
+        _ => Err(format!("Cannot combine {} and {}", left, right)),
+    }
+}
+
+// An amount as it is written in an expression, such as `$10.5`. Without a journal there is no
+// style to show it in.
+fn amount_string(amount: &Amount) -> String {
+    format!("{}{}", amount.symbol(), number_string(amount.quantity()))
+}
+
+// A number as a decimal, with as many digits after the point as it needs up to ten.
+fn number_string(n: &Quantity) -> String {
+    let precision = (0..10).find(|&precision| (n * Quantity::from_integer(pow(BigInt::from(10), precision))).is_integer()).unwrap_or(10);
+    format_quantity(n, precision)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(Quantity),
+    Amount(Amount),
+    String(String),
+    Regex(String),
+    Date(NaiveDate),
+    Identifier(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Number(ref n) => write!(f, "{}", number_string(n)),
+            Token::Amount(ref amount) => write!(f, "{}{}", amount.symbol(), format_quantity(amount.quantity(), amount.precision())),
+            Token::String(ref s) => write!(f, "\"{}\"", s),
+            Token::Regex(ref s) => write!(f, "/{}/", s),
+            Token::Date(date) => write!(f, "[{}]", date),
+            Token::Identifier(ref name) => write!(f, "{}", name),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 19] = ["==", "!=", "<=", ">=", "=~", "&&", "||", "<", ">", "!", "+", "-", "*", "/", "(", ")", ",", ".", "="];
+
+fn scan(text: &str) -> Result<Vec<Token>, String> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let rest = chars[i..].iter().collect::<String>();
+        // After an operand a slash divides; anywhere else it starts a regular expression.
+        let after_operand = match tokens.last() {
+            Some(Token::Symbol(symbol)) => *symbol == ")",
+            Some(Token::Identifier(word)) => !matches!(word.as_str(), "and" | "or" | "not"),
+            Some(_) => true,
+            None => false,
+        };
+        if c.is_whitespace() {
+            i += 1;
+        }
+        else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit())) {
+            let length = number_length(&chars[i..]);
+            tokens.push(Token::Number(number(&chars[i..i + length])?));
+            i += length;
+        }
+        else if c == '"' || c == '[' || (c == '/' && !after_operand) {
+            let close = match c { '[' => ']', other => other };
+            let end = chars[i + 1..].iter().position(|&d| d == close).ok_or_else(|| format!("Expected closing {}", close))?;
+            let inner = chars[i + 1..i + 1 + end].iter().collect::<String>();
+            tokens.push(match c {
+                '"' => Token::String(inner),
+                '[' => Token::Date(parse_date(inner.trim())?),
+                _ => Token::Regex(inner),
+            });
+            i += end + 2;
+        }
+        else if c.is_alphabetic() || c == '_' {
+            let length = chars[i..].iter().take_while(|c| c.is_alphanumeric() || **c == '_').count();
+            tokens.push(Token::Identifier(chars[i..i + length].iter().collect()));
+            i += length;
+        }
+        else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            i += symbol.chars().count();
+        }
+        else if chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) {
+            // A symbol such as `$` straight before a number makes an amount.
+            let length = number_length(&chars[i + 1..]);
+            let text = chars[i + 1..i + 1 + length].iter().filter(|c| **c != ',').collect::<String>();
+            tokens.push(Token::Amount(Amount::parse(CommodityId::intern(&c.to_string()), &text)?));
+            i += length + 1;
+        }
+        else {
+            return Err(format!("Unexpected '{}' in expression", c));
+        }
+    }
+    Ok(tokens)
+}
+
+fn number_length(chars: &[char]) -> usize {
+    chars.iter().take_while(|c| c.is_ascii_digit() || **c == '.' || **c == ',').count()
+}
+
+fn number(chars: &[char]) -> Result<Quantity, String> {
+    let text = chars.iter().filter(|c| **c != ',').collect::<String>();
+    parse_quantity(&text).map(|(quantity, _)| quantity).map_err(|_| format!("Invalid number '{}'", text))
+}
+
+struct ValueParser {
+    tokens: Vec<Token>,
+    current: usize,
+}
+
+impl ValueParser {
+    fn or(&mut self) -> Result<ValueExpr, String> {
+        let mut expr = self.and()?;
+        while self.match_word("or") || self.match_symbol("||") {
+            expr = binary_expr(expr, "or", self.and()?);
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<ValueExpr, String> {
+        let mut expr = self.not()?;
+        while self.match_word("and") || self.match_symbol("&&") {
+            expr = binary_expr(expr, "and", self.not()?);
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<ValueExpr, String> {
+        if self.match_word("not") || self.match_symbol("!") {
+            return Ok(ValueExpr::Unary { operator: "not".to_string(), right: Box::new(self.not()?) });
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<ValueExpr, String> {
+        let expr = self.term()?;
+        for operator in &["==", "!=", "<=", ">=", "<", ">", "=~", "="] {
+            if self.match_symbol(operator) {
+                // Ledger also accepts a single `=` for equality.
+                let operator = if *operator == "=" { "==" } else { operator };
+                return Ok(binary_expr(expr, operator, self.term()?));
+            }
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<ValueExpr, String> {
+        let mut expr = self.factor()?;
+        loop {
+            let operator = if self.match_symbol("+") { "+" } else if self.match_symbol("-") { "-" } else { break };
+            expr = binary_expr(expr, operator, self.factor()?);
+        }
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> Result<ValueExpr, String> {
+        let mut expr = self.unary()?;
+        loop {
+            let operator = if self.match_symbol("*") { "*" } else if self.match_symbol("/") { "/" } else { break };
+            expr = binary_expr(expr, operator, self.unary()?);
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<ValueExpr, String> {
+        if self.match_symbol("-") {
+            return Ok(ValueExpr::Unary { operator: "-".to_string(), right: Box::new(self.unary()?) });
+        }
+        self.call()
+    }
+
+    fn call(&mut self) -> Result<ValueExpr, String> {
+        let mut expr = self.primary()?;
+        while self.match_symbol(".") {
+            match self.advance() {
+                Some(Token::Identifier(field)) => expr = ValueExpr::Field { value: Box::new(expr), field },
+                _ => return Err("Expected a name after '.'".to_string()),
+            }
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<ValueExpr, String> {
+        let token = self.advance().ok_or("Expected an expression")?;
+        match token {
+            Token::Number(n) => Ok(ValueExpr::Literal(Value::Number(n))),
+            Token::Amount(amount) => Ok(ValueExpr::Literal(Value::Amount(amount))),
+            Token::String(s) => Ok(ValueExpr::Literal(Value::String(s))),
+            Token::Date(date) => Ok(ValueExpr::Literal(Value::Date(date))),
+            Token::Regex(pattern) => {
+                let regex = Regex::new(&pattern).map_err(|err| format!("Invalid pattern '{}': {}", pattern, err))?;
+                Ok(ValueExpr::Literal(Value::Regex(regex)))
+            }
+            Token::Identifier(ref word) if word == "true" || word == "false" => Ok(ValueExpr::Literal(Value::Bool(word == "true"))),
+            Token::Identifier(name) => {
+                if !self.match_symbol("(") {
+                    return Ok(ValueExpr::Variable(name));
+                }
+                let mut arguments = Vec::new();
+                if !self.match_symbol(")") {
+                    loop {
+                        arguments.push(self.or()?);
+                        if self.match_symbol(")") {
+                            break;
+                        }
+                        if !self.match_symbol(",") {
+                            return Err(format!("Expected ',' or ')' after arguments to {}", name));
+                        }
+                    }
+                }
+                Ok(ValueExpr::Call { function: name, arguments })
+            }
+            Token::Symbol("(") => {
+                let expr = self.or()?;
+                if !self.match_symbol(")") {
+                    return Err("Expected ')' after expression".to_string());
+                }
+                Ok(expr)
+            }
+            other => Err(format!("Unexpected '{}' in expression", other)),
+        }
+    }
+
+    fn match_symbol(&mut self, symbol: &str) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol_str(symbol))) {
+            self.current += 1;
+            return true;
+        }
+        false
+    }
+
+    fn match_word(&mut self, word: &str) -> bool {
+        if let Some(Token::Identifier(name)) = self.peek() {
+            if name == word {
+                self.current += 1;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.current).cloned();
+        self.current += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.current)
+    }
+}
+
+fn symbol_str(symbol: &str) -> &'static str {
+    SYMBOLS.iter().find(|s| **s == symbol).cloned().unwrap_or("")
+}
+
+fn binary_expr(left: ValueExpr, operator: &str, right: ValueExpr) -> ValueExpr {
+    ValueExpr::Binary { left: Box::new(left), operator: operator.to_string(), right: Box::new(right) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::lex;
+    use parser::parse;
+
+    fn evaluate(expression: &str, journal: &Journal, posting: Option<&Posting>) -> Result<bool, String> {
+        Ok(ValueExpr::parse(expression)?.evaluate(journal, posting)?.is_true())
+    }
+
+    #[test]
+    fn test_account_totals() {
+        let journal = parse(lex("2013/01/04 Lunch\n  Expenses:Food  $10\n  Assets:Cash\n\n2013/01/05 Taxi\n  Expenses:Travel  20 EUR\n  Assets:Card\n"));
+        assert!(evaluate("account(\"Expenses:Food\").total == $10", &journal, None).unwrap());
+        assert!(evaluate("account(\"Assets:Cash\").total >= 0", &journal, None).is_ok_and(|b| !b));
+        assert!(evaluate("account(\"Expenses\").total > 5 and account(\"Assets\").total < 0", &journal, None).unwrap());
+        assert!(evaluate("account(\"Income\").total == 0", &journal, None).unwrap());
+        assert!(evaluate("account(\"Expenses:Travel\").total + $5", &journal, None).is_err());
+        assert!(evaluate("amount > 0", &journal, None).is_err());
+    }
+
+    #[test]
+    fn test_posting_variables() {
+        let journal = parse(lex("2013/01/04 Lunch\n  Expenses:Food  $10  ; receipt\n  Assets:Cash\n"));
+        let posting = &journal.postings()[0];
+        assert!(evaluate("commodity == \"$\"", &journal, Some(posting)).unwrap());
+        assert!(evaluate("abs(amount) <= $100 && payee =~ /^Lunch/", &journal, Some(posting)).unwrap());
+        assert!(evaluate("date >= [2013/01/01] and not (note == \"\")", &journal, Some(posting)).unwrap());
+        assert!(evaluate("quantity(amount) * 2 / 4 = 5", &journal, Some(posting)).unwrap());
+        assert!(evaluate("amount / $2 == 5 and commodity(amount / $2) == \"\"", &journal, Some(posting)).unwrap());
+        assert!(evaluate("amount / 2 == $5", &journal, Some(posting)).unwrap());
+        assert!(evaluate("amount / 0", &journal, Some(posting)).is_err());
+        assert!(ValueExpr::parse("amount >").is_err());
+        assert!(ValueExpr::parse("(amount").is_err());
+    }
+}